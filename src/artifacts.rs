@@ -0,0 +1,318 @@
+use std::fs;
+use image::GrayImage;
+use imageproc::distance_transform::{distance_transform, Norm};
+use crate::plane::gray_to_plane;
+
+// JPEG COMPRESSION ARTIFACTS
+
+const BLOCK_SIZE: usize = 8;
+
+// canny thresholds for the edges ringing is measured around, only strong edges ring visibly
+const STRONG_EDGE_LOW: f32 = 60.0;
+const STRONG_EDGE_HIGH: f32 = 120.0;
+// band around strong edges (in pixels) where ringing shows up, and the distance after which
+// a pixel is considered to be away from any edge
+const RINGING_BAND: (u8, u8) = (2, 4);
+const RINGING_FAR: u8 = 8;
+
+// the example quantization tables from annex K of the JPEG standard (ITU-T T.81),
+// in natural (row by row) order. libjpeg scales these for every quality setting
+const STANDARD_LUMINANCE_TABLE: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61,
+    12, 12, 14, 19, 26, 58, 60, 55,
+    14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62,
+    18, 22, 37, 56, 68, 109, 103, 77,
+    24, 35, 55, 64, 81, 104, 113, 92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+const STANDARD_CHROMINANCE_TABLE: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99,
+    18, 21, 26, 66, 99, 99, 99, 99,
+    24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+// position in natural order of the n-th coefficient stored in a DQT segment
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10,
+    17, 24, 32, 25, 18, 11, 4, 5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+// ratio of the mean absolute luminance step across 8x8 block boundaries to the mean step
+// inside the blocks, averaged over the horizontal and vertical direction.
+// values close to 1 mean no visible blocking, heavily compressed images go well above 1
+// similar in spirit to Z. Wang, A. C. Bovik, B. L. Evans,
+// "Blind measurement of blocking artifacts in images", ICIP 2000
+pub fn blockiness(pixels: &GrayImage) -> f32 {
+    let plane = gray_to_plane(pixels);
+    let width = plane.width();
+    // no steps to compare, same as an image without blocking
+    if plane.is_empty() {
+        return 1.0;
+    }
+
+    let mut boundary = (0.0f64, 0usize);
+    let mut interior = (0.0f64, 0usize);
+
+    // horizontal steps, between column x and x + 1
+    for row in plane.rows() {
+        for x in 0..(width - 1) {
+            let step = (row[x + 1] - row[x]).abs() as f64;
+            if x % BLOCK_SIZE == BLOCK_SIZE - 1 {
+                boundary = (boundary.0 + step, boundary.1 + 1);
+            } else {
+                interior = (interior.0 + step, interior.1 + 1);
+            }
+        }
+    }
+
+    // vertical steps, between row y and y + 1
+    for (y, (row, next)) in plane.rows().zip(plane.rows().skip(1)).enumerate() {
+        for (above, below) in row.iter().zip(next.iter()) {
+            let step = (below - above).abs() as f64;
+            if y % BLOCK_SIZE == BLOCK_SIZE - 1 {
+                boundary = (boundary.0 + step, boundary.1 + 1);
+            } else {
+                interior = (interior.0 + step, interior.1 + 1);
+            }
+        }
+    }
+
+    if boundary.1 == 0 || interior.1 == 0 || interior.0 == 0.0 {
+        return 1.0;
+    }
+
+    ((boundary.0 / boundary.1 as f64) / (interior.0 / interior.1 as f64)) as f32
+}
+
+// ringing estimate: mean absolute laplacian in a thin band next to strong edges, relative to
+// the same activity measured far away from any edge. Ringing (mosquito noise) adds oscillations
+// right next to edges, so compressed images score above 1
+pub fn ringing(pixels: &GrayImage) -> f32 {
+    let plane = gray_to_plane(pixels);
+    let (width, height) = pixels.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+
+    let edges = imageproc::edges::canny(pixels, STRONG_EDGE_LOW, STRONG_EDGE_HIGH);
+    let distance = distance_transform(&edges, Norm::LInf);
+
+    let mut band = (0.0f64, 0usize);
+    let mut far = (0.0f64, 0usize);
+    for y in 1..(height as usize - 1) {
+        for x in 1..(width as usize - 1) {
+            let laplacian = (4.0 * plane[(x, y)]
+                - plane[(x, y - 1)] - plane[(x, y + 1)]
+                - plane[(x - 1, y)] - plane[(x + 1, y)]).abs() as f64;
+            let d = distance.get_pixel(x as u32, y as u32)[0];
+            if d >= RINGING_BAND.0 && d <= RINGING_BAND.1 {
+                band = (band.0 + laplacian, band.1 + 1);
+            } else if d > RINGING_FAR {
+                far = (far.0 + laplacian, far.1 + 1);
+            }
+        }
+    }
+
+    // no strong edges, nothing can ring
+    if band.1 == 0 {
+        return 0.0;
+    }
+    let band_mean = band.0 / band.1 as f64;
+    let far_mean = if far.1 == 0 { 0.0 } else { far.0 / far.1 as f64 };
+
+    (band_mean / far_mean.max(1.0)) as f32
+}
+
+// quantization tables stored in a jpeg file, indexed by their table id (0 - 3).
+// values are converted to natural order. Returns None if the data is not a jpeg
+pub fn jpeg_quantization_tables(data: &[u8]) -> Option<Vec<Option<[u16; 64]>>> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] != 0xD8 {
+        return None;
+    }
+
+    let mut tables = vec![None; 4];
+    let mut i = 2;
+    while i + 4 <= data.len() {
+        if data[i] != 0xFF {
+            return Some(tables);
+        }
+        let marker = data[i + 1];
+        // fill bytes
+        if marker == 0xFF {
+            i += 1;
+            continue;
+        }
+        // markers without a length field
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            i += 2;
+            continue;
+        }
+        // start of scan, the tables all come before entropy coded data in practice
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+
+        let length = ((data[i + 2] as usize) << 8) | data[i + 3] as usize;
+        let end = (i + 2 + length).min(data.len());
+
+        if marker == 0xDB {
+            let mut j = i + 4;
+            while j < end {
+                let precision = data[j] >> 4;
+                let id = (data[j] & 0x0F) as usize;
+                j += 1;
+                let mut table = [0u16; 64];
+                for position in ZIGZAG {
+                    if precision == 0 {
+                        if j >= end {
+                            return Some(tables);
+                        }
+                        table[position] = data[j] as u16;
+                        j += 1;
+                    } else {
+                        if j + 1 >= end {
+                            return Some(tables);
+                        }
+                        table[position] = ((data[j] as u16) << 8) | data[j + 1] as u16;
+                        j += 2;
+                    }
+                }
+                if id < tables.len() {
+                    tables[id] = Some(table);
+                }
+            }
+        }
+
+        i = end;
+    }
+
+    Some(tables)
+}
+
+// table libjpeg produces for the given quality from the standard table
+fn scaled_table(standard: &[u16; 64], quality: u32) -> [u16; 64] {
+    let scale = if quality < 50 { 5000 / quality } else { 200 - 2 * quality };
+    standard.map(|q| ((q as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+// estimated IJG quality factor (1 - 100) of a jpeg, found as the quality whose scaled standard
+// tables are closest to the tables stored in the file. Returns None for anything that is
+// not a jpeg or has no quantization tables
+pub fn estimate_jpeg_quality(data: &[u8]) -> Option<u32> {
+    let tables = jpeg_quantization_tables(data)?;
+    let luminance = tables[0]?;
+    let chrominance = tables[1];
+
+    let mut best = (u64::MAX, 0);
+    for quality in 1..=100 {
+        let mut error = table_distance(&luminance, &scaled_table(&STANDARD_LUMINANCE_TABLE, quality));
+        if let Some(chrominance) = chrominance {
+            error += table_distance(&chrominance, &scaled_table(&STANDARD_CHROMINANCE_TABLE, quality));
+        }
+        if error < best.0 {
+            best = (error, quality);
+        }
+    }
+
+    Some(best.1)
+}
+
+pub fn estimate_jpeg_quality_from_file(path: &str) -> Option<u32> {
+    let data = fs::read(path).ok()?;
+    estimate_jpeg_quality(&data)
+}
+
+fn table_distance(a: &[u16; 64], b: &[u16; 64]) -> u64 {
+    a.iter().zip(b.iter()).map(|(x, y)| (*x as i64 - *y as i64).unsigned_abs()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{ColorType, Luma};
+
+    // gray image encoded as a jpeg with the given quality
+    fn encoded_jpeg(quality: u8) -> Vec<u8> {
+        let image = GrayImage::from_fn(64, 64, |x, y| Luma([((x * 3 + y * 2) % 256) as u8]));
+        let mut data = Vec::new();
+        JpegEncoder::new_with_quality(&mut data, quality)
+            .encode(image.as_raw(), 64, 64, ColorType::L8)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn smooth_ramp_has_no_blocking() {
+        let ramp = GrayImage::from_fn(64, 64, |x, y| Luma([(x + y) as u8]));
+        assert_eq!(blockiness(&ramp), 1.0);
+        assert_eq!(blockiness(&GrayImage::from_pixel(64, 64, Luma([90]))), 1.0);
+    }
+
+    #[test]
+    fn block_pattern_is_blocky() {
+        // every 8x8 block is flat with its own level, plus a one level texture inside the blocks
+        let blocks = GrayImage::from_fn(64, 64, |x, y| {
+            let level = 60 + 40 * ((x / 8 + y / 8) % 3);
+            Luma([(level + (x + y) % 2) as u8])
+        });
+        // boundary steps are 40 or 80 levels against interior steps of 1
+        assert!(blockiness(&blocks) > 20.0, "{}", blockiness(&blocks));
+    }
+
+    #[test]
+    fn empty_images_have_no_artifacts() {
+        assert_eq!(blockiness(&GrayImage::new(0, 8)), 1.0);
+        assert_eq!(blockiness(&GrayImage::new(8, 0)), 1.0);
+        assert_eq!(ringing(&GrayImage::new(0, 0)), 0.0);
+    }
+
+    #[test]
+    fn flat_image_does_not_ring() {
+        assert_eq!(ringing(&GrayImage::from_pixel(32, 32, Luma([200]))), 0.0);
+    }
+
+    #[test]
+    fn overshoot_next_to_an_edge_rings() {
+        // vertical step from 50 to 200 at x = 32, the ringing version oscillates by 20 levels
+        // in the columns 2 to 4 pixels away from it on both sides
+        let step = |ripple: i32| GrayImage::from_fn(64, 64, |x, _| {
+            let level = if x < 32 { 50 } else { 200 };
+            let distance = if x < 32 { 31 - x as i32 } else { x as i32 - 32 };
+            let wave = if (1..=4).contains(&distance) { ripple * if distance % 2 == 0 { 1 } else { -1 } } else { 0 };
+            Luma([(level + wave) as u8])
+        });
+        // a clean step is flat in the band, its laplacian is 0 there
+        assert_eq!(ringing(&step(0)), 0.0);
+        assert!(ringing(&step(20)) > 10.0, "{}", ringing(&step(20)));
+    }
+
+    #[test]
+    fn jpeg_quality_is_recovered_from_the_tables() {
+        for quality in [30, 50, 75, 90] {
+            assert_eq!(estimate_jpeg_quality(&encoded_jpeg(quality)), Some(quality as u32));
+        }
+    }
+
+    #[test]
+    fn quantization_tables_need_a_jpeg() {
+        assert!(jpeg_quantization_tables(b"\x89PNG\r\n\x1a\n").is_none());
+        let tables = jpeg_quantization_tables(&encoded_jpeg(50)).unwrap();
+        // quality 50 is the standard table itself
+        assert_eq!(tables[0], Some(STANDARD_LUMINANCE_TABLE));
+    }
+}
@@ -0,0 +1,260 @@
+use std::path::{Path, PathBuf};
+use image::ImageError;
+use rayon::prelude::*;
+use crate::colorfulness::Luminance;
+use crate::context::AnalysisContext;
+use crate::feature_cache::{content_hash, parameter_hash, CacheEntry, FeatureCache};
+use crate::hdr::is_scene_linear;
+use crate::loader::{load_image, read_metadata, ImageMetadata, LoadOptions, LoadedImage, METADATA_COLUMNS};
+use crate::mask::Mask;
+use crate::metrics::Metric;
+use crate::preprocess::{filter_name, preprocess, ResizePolicy};
+
+// BATCH PROCESSING
+// files are analyzed in parallel, a chunk at a time so only a bounded number of decoded images
+// and results are held at once. Rows come out in the order of the input paths no matter how
+// many threads run. With a feature cache only the values missing from it are computed, a file
+// whose values are all cached is not even decoded, only its EXIF fields are read
+
+const IMAGE_EXTENSIONS: [&str; 9] = ["jpg", "jpeg", "png", "tif", "tiff", "bmp", "webp", "hdr", "exr"];
+
+pub struct BatchOptions {
+    pub metrics: Vec<Metric>,
+    pub luminance: Luminance,
+    pub policy: ResizePolicy,
+    pub load: LoadOptions,
+    // files analyzed per chunk, a few per thread keeps every thread busy
+    pub chunk_size: usize,
+}
+
+pub struct BatchRow {
+    pub path: PathBuf,
+    pub original: (u32, u32),
+    pub analysis: (u32, u32),
+    pub metadata: ImageMetadata,
+    // one value per metric, same order as BatchOptions::metrics
+    pub values: Vec<f32>,
+    pub content_hash: u64,
+    // float input, the tone mapping is part of its cache key
+    pub scene_linear: bool,
+    // per value, whether it came from the feature cache
+    pub from_cache: Vec<bool>,
+}
+
+impl BatchRow {
+    // options are the ones the row was computed with, their luminance method and resize policy
+    // are written next to the sizes. The tone mapping is left empty for display referred files,
+    // it does not apply to them
+    pub fn csv_line(&self, options: &BatchOptions) -> String {
+        let policy = &options.policy;
+        let mut fields = vec![
+            self.path.display().to_string(),
+            self.original.0.to_string(),
+            self.original.1.to_string(),
+            self.analysis.0.to_string(),
+            self.analysis.1.to_string(),
+            options.luminance.name().to_string(),
+            policy.size.map_or("none".to_string(), |size| size.name()),
+            filter_name(policy.filter).to_string(),
+            policy.framing.name(),
+            if self.scene_linear { policy.tone_mapping.name() } else { String::new() },
+        ];
+        fields.extend(self.metadata.csv_fields());
+        fields.extend(self.values.iter().map(|v| v.to_string()));
+        fields.join(",")
+    }
+}
+
+pub fn csv_header(metrics: &[Metric]) -> String {
+    let mut fields = vec![
+        "path", "original_width", "original_height", "analysis_width", "analysis_height",
+        "luminance", "resize", "filter", "framing", "tone_mapping",
+    ];
+    fields.extend(METADATA_COLUMNS);
+    fields.extend(metrics.iter().map(|metric| metric.name()));
+    fields.join(",")
+}
+
+// image files directly inside the directory, sorted by name
+pub fn image_paths(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+pub fn analyze_file(path: &Path, options: &BatchOptions, cache: Option<&FeatureCache>) -> Result<BatchRow, ImageError> {
+    let bytes = std::fs::read(path)?;
+    let content_hash = content_hash(&bytes);
+    let scene_linear = is_scene_linear(&bytes);
+    let cached: Vec<Option<&CacheEntry>> = options.metrics.iter()
+        .map(|metric| {
+            let parameters = parameter_hash(*metric, options.luminance, &options.policy, &options.load, scene_linear);
+            cache.and_then(|cache| cache.get(content_hash, *metric, parameters))
+        })
+        .collect();
+    let from_cache = cached.iter().map(|entry| entry.is_some()).collect();
+
+    if let Some(Some(entry)) = cached.first() {
+        if cached.iter().all(|entry| entry.is_some()) {
+            return Ok(BatchRow {
+                path: path.to_path_buf(),
+                original: entry.original,
+                analysis: entry.analysis,
+                metadata: read_metadata(&bytes),
+                values: cached.iter().map(|entry| entry.unwrap().value).collect(),
+                content_hash,
+                scene_linear,
+                from_cache,
+            });
+        }
+    }
+
+    let LoadedImage { image: decoded, metadata, .. } = load_image(&bytes, &options.load)?;
+    drop(bytes);
+    let preprocessed = preprocess(&decoded, &options.policy);
+    drop(decoded);
+    // transparent pixels, e.g. the letterbox bars, are left out
+    let mask = Mask::from_alpha(&preprocessed.image);
+    let image = preprocessed.image.to_rgb32f();
+    let context = AnalysisContext::with_precision(&image, options.luminance, preprocessed.input.high_precision());
+
+    Ok(BatchRow {
+        path: path.to_path_buf(),
+        original: preprocessed.original,
+        analysis: preprocessed.analysis,
+        metadata,
+        values: options.metrics.iter()
+            .zip(cached)
+            .map(|(metric, entry)| entry.map_or_else(|| metric.compute_with(&context, mask.as_ref()), |entry| entry.value))
+            .collect(),
+        content_hash,
+        scene_linear,
+        from_cache,
+    })
+}
+
+// on_row gets every file in input order, as soon as its chunk is done. Newly computed values are
+// added to the cache after every chunk, so an interrupted run keeps what it finished
+pub fn run_batch(
+    paths: &[PathBuf],
+    options: &BatchOptions,
+    mut cache: Option<&mut FeatureCache>,
+    mut on_row: impl FnMut(&Path, Result<BatchRow, ImageError>),
+) -> std::io::Result<()> {
+    for chunk in paths.chunks(options.chunk_size.max(1)) {
+        let reader = cache.as_deref();
+        let results: Vec<Result<BatchRow, ImageError>> = chunk.par_iter()
+            .map(|path| analyze_file(path, options, reader))
+            .collect();
+
+        if let Some(cache) = cache.as_deref_mut() {
+            let mut new_entries = Vec::new();
+            for row in results.iter().flatten() {
+                for ((metric, value), from_cache) in options.metrics.iter().zip(&row.values).zip(&row.from_cache) {
+                    if !from_cache {
+                        new_entries.push(CacheEntry {
+                            content_hash: row.content_hash,
+                            metric: metric.name().to_string(),
+                            parameters: parameter_hash(*metric, options.luminance, &options.policy, &options.load, row.scene_linear),
+                            original: row.original,
+                            analysis: row.analysis,
+                            value: *value,
+                            path: row.path.clone(),
+                        });
+                    }
+                }
+            }
+            cache.insert(new_entries)?;
+        }
+
+        for (path, result) in chunk.iter().zip(results) {
+            on_row(path, result);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocess::Framing;
+    use crate::utils::Lcg;
+
+    #[test]
+    fn rows_come_out_in_input_order() {
+        let directory = std::env::temp_dir().join(format!("batch_order_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        // five flat images, gray level 40 * i, plus a file that is not an image
+        let mut paths = Vec::new();
+        for i in 0..5u8 {
+            let path = directory.join(format!("{}.png", i));
+            image::GrayImage::from_pixel(16, 8, image::Luma([40 * i])).save(&path).unwrap();
+            paths.push(path);
+        }
+        std::fs::write(directory.join("notes.txt"), "not an image").unwrap();
+        assert_eq!(image_paths(&directory).unwrap(), paths);
+
+        let options = BatchOptions {
+            metrics: vec![Metric::GrayscaleSd, Metric::Entropy],
+            luminance: Luminance::Rec709,
+            policy: ResizePolicy::default(),
+            load: LoadOptions::default(),
+            chunk_size: 2,
+        };
+        let mut rows = Vec::new();
+        run_batch(&paths, &options, None, |path, row| rows.push((path.to_path_buf(), row.unwrap()))).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        for (i, (path, row)) in rows.iter().enumerate() {
+            assert_eq!((path, &row.path), (&paths[i], &paths[i]));
+            assert_eq!(row.original, (16, 8));
+            // the rms of a flat image is its gray level
+            assert!((row.values[0] - (40 * i) as f32 / 255.0).abs() < 1e-3, "{:?}", row.values);
+            assert_eq!(row.values[1], 0.0);
+            assert!(row.from_cache.iter().all(|cached| !cached));
+        }
+        assert_eq!(rows.len(), 5);
+
+        // luminance method and resize policy are recorded, the tone mapping only for float files
+        let header = csv_header(&options.metrics);
+        let line = rows[1].1.csv_line(&options);
+        assert_eq!(header.split(',').count(), line.split(',').count());
+        assert!(header.contains(",luminance,resize,filter,framing,tone_mapping,"));
+        assert!(line.contains(",rec709,none,lanczos3,full,,"), "{}", line);
+    }
+
+    #[test]
+    fn letterbox_bars_are_left_out() {
+        let path = std::env::temp_dir().join(format!("batch_letterbox_{}.png", std::process::id()));
+        let mut rng = Lcg(5);
+        image::RgbImage::from_fn(40, 24, |x, y| {
+            image::Rgb([rng.byte(), (x * 6) as u8, (y * 10) as u8])
+        }).save(&path).unwrap();
+
+        let options = |framing| BatchOptions {
+            metrics: Metric::ALL.to_vec(),
+            luminance: Luminance::Rec709,
+            policy: ResizePolicy { framing, ..ResizePolicy::default() },
+            load: LoadOptions::default(),
+            chunk_size: 1,
+        };
+        let plain = analyze_file(&path, &options(Framing::Full), None).unwrap();
+        let letterboxed = analyze_file(&path, &options(Framing::Letterbox { aspect_ratio: 1.0 }), None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(letterboxed.analysis, (40, 40));
+        for (metric, (plain, letterboxed)) in Metric::ALL.iter().zip(plain.values.iter().zip(&letterboxed.values)) {
+            // canny still finds the border of the bars, next to it the edges differ
+            if *metric != Metric::EdgeDensity {
+                assert_eq!(plain, letterboxed, "{}", metric.name());
+            }
+        }
+    }
+}
@@ -1,11 +1,16 @@
 use std::collections::HashSet;
 use float_cmp::approx_eq;
-use image::{Rgb32FImage, RgbImage};
-use crate::utils::{mean, std_dev, std_dev_2d_vec};
+use image::{GrayImage, Rgb32FImage, RgbImage};
+use crate::mask::{included, Mask};
+use rayon::prelude::*;
+use crate::plane::Plane;
+use crate::simd::{gray_row, rgb_to_lab_row};
+use crate::utils::{mean, srgb_to_linear, std_dev, std_dev_plane, to_8bit};
 
 // L ranges from 0 to 100
 // a ranges from -128 to 127
 // b ranges from -128 to 127
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct LabPixel {
     pub l: f32,
     pub a: f32,
@@ -71,9 +76,9 @@ pub fn lab_to_rgb(l: f32, a: f32, b: f32) -> [f32; 3] {
     [r, g, b]
 }
 
-pub fn lab_to_rgb_image(image: &Vec<Vec<LabPixel>>) -> Rgb32FImage {
-    let mut output = Rgb32FImage::new(image[0].len() as u32, image.len() as u32);
-    for (i, row) in image.iter().enumerate() {
+pub fn lab_to_rgb_image(image: &Plane<LabPixel>) -> Rgb32FImage {
+    let mut output = Rgb32FImage::new(image.width() as u32, image.height() as u32);
+    for (i, row) in image.rows().enumerate() {
         for (j, pixel) in row.iter().enumerate() {
             let rgb = lab_to_rgb(pixel.l, pixel.a, pixel.b);
             output.put_pixel(j as u32, i as u32, image::Rgb(rgb));
@@ -82,17 +87,17 @@ pub fn lab_to_rgb_image(image: &Vec<Vec<LabPixel>>) -> Rgb32FImage {
     output
 }
 
-// converts an image from RGB to an vec of cieLAB pixels
-pub fn rgb_to_lab_image(image: &Rgb32FImage) -> Vec<Vec<LabPixel>> {
-    let mut output = Vec::new();
-    for (i, row) in image.rows().enumerate() {
-        let mut row_vec = Vec::new();
-        for (j, pixel) in row.enumerate() {
-            let rgb = pixel.0;
-            let lab = rgb_to_lab(rgb[0], rgb[1], rgb[2]);
-            row_vec.push(lab);
-        }
-        output.push(row_vec);
+// converts an image from RGB to a plane of cieLAB pixels
+// rows are converted in parallel, with simd where available
+pub fn rgb_to_lab_image(image: &Rgb32FImage) -> Plane<LabPixel> {
+    let width = image.width() as usize;
+    let mut output = Plane::new(width, image.height() as usize);
+    if width > 0 {
+        let stride = output.stride();
+        output.as_mut_slice()
+            .par_chunks_mut(stride)
+            .zip(image.as_raw().par_chunks_exact(width * 3))
+            .for_each(|(out, rgb)| rgb_to_lab_row(rgb, &mut out[..width]));
     }
     output
 }
@@ -101,35 +106,49 @@ pub fn chroma(lab: &LabPixel) -> f32 {
     (lab.a.powi(2) + lab.b.powi(2)).sqrt()
 }
 
-pub fn mean_of_chroma(image: &Vec<Vec<LabPixel>>) -> f32 {
+pub fn mean_of_chroma(image: &Plane<LabPixel>, mask: Option<&Mask>) -> f32 {
     let mut sum = 0.0;
-    for row in image {
-        for pixel in row {
+    let mut count = 0;
+    for (y, row) in image.rows().enumerate() {
+        for (x, pixel) in row.iter().enumerate() {
+            if !included(mask, x as u32, y as u32) {
+                continue;
+            }
             sum += chroma(pixel);
+            count += 1;
         }
     }
-    sum / (image.len() * image[0].len()) as f32
+    sum / count.max(1) as f32
 }
 
 // this returns the colorfulness metrics one and three from table one from:
 // https://www.researchgate.net/publication/243135534_Measuring_Colourfulness_in_Natural_Images
 // metric one is standard deviations of a and b in CIELAB color space + the mean of Chroma
 // metric two is the trigonometric len between standard deviations of a and b + the mean of chroma
-pub fn colorfulness_metrics_1_3(image: &Vec<Vec<LabPixel>>) -> (f32, f32) {
+// with a mask only the included pixels are used
+pub fn colorfulness_metrics_1_3(image: &Plane<LabPixel>, mask: Option<&Mask>) -> (f32, f32) {
     let mut output_1 = 0.0f32;
     let mut output_3 = 0.0f32;
-    let mean_of_chroma = mean_of_chroma(image);
+    let mean_of_chroma = mean_of_chroma(image, mask);
 
     let mut vec_a = Vec::new();
     let mut vec_b = Vec::new();
 
-    for row in image {
-        for pixel in row {
+    for (y, row) in image.rows().enumerate() {
+        for (x, pixel) in row.iter().enumerate() {
+            if !included(mask, x as u32, y as u32) {
+                continue;
+            }
             vec_a.push(pixel.a);
             vec_b.push(pixel.b);
         }
     }
 
+    // empty mask
+    if vec_a.is_empty() {
+        return (0.0, 0.0);
+    }
+
     let std_dev_of_a = std_dev(&vec_a);
     let std_dev_of_b = std_dev(&vec_b);
 
@@ -157,36 +176,143 @@ pub fn lab_saturation(lab: &LabPixel) -> f32 {
 
 // calculates colorfulness metric three from table 1 from:
 // https://dl.acm.org/doi/pdf/10.1145/2470654.2481281
-pub fn colorfulness_metrics_2(image: &Vec<Vec<LabPixel>>) -> f32 {
+// with a mask only the included pixels are used
+pub fn colorfulness_metrics_2(image: &Plane<LabPixel>, mask: Option<&Mask>) -> f32 {
     let mut output = 0.0f32;
 
     // calculate saturation of each pixel
     let mut vec_s = Vec::new();
-    for row in image {
-        for pixel in row {
-            vec_s.push(lab_saturation(pixel));
+    for (y, row) in image.rows().enumerate() {
+        for (x, pixel) in row.iter().enumerate() {
+            if included(mask, x as u32, y as u32) {
+                vec_s.push(lab_saturation(pixel));
+            }
         }
     }
 
+    // empty mask
+    if vec_s.is_empty() {
+        return 0.0;
+    }
+
     output = mean(&vec_s) + std_dev(&vec_s);
 
     output
 }
 
-pub fn grayscale(image: &Rgb32FImage) -> Vec<Vec<f32>> {
-    let mut output = Vec::new();
-    for (i, pixel) in image.pixels().enumerate() {
-        let gray = (pixel[0] + pixel[1] + pixel[2]) / 3.0;
-        if i % image.width() as usize == 0 {
-            output.push(Vec::new());
+// how a color pixel is reduced to a single gray value.
+// every metric working on gray input should get its gray image from grayscale() (or its
+// quantize_gray() version) with the same method, otherwise the results are not comparable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Luminance {
+    // (r + g + b) / 3
+    Average,
+    // Rec. 601 luma weights on the gamma encoded values
+    Rec601,
+    // Rec. 709 luma weights on the gamma encoded values, same as image's to_luma8
+    Rec709,
+    // Rec. 709 weights on linearized values, i.e. relative luminance Y (stays linear)
+    Rec709Linear,
+    // CIELAB L* scaled to [0, 1]
+    LabL,
+    // HSV value, max(r, g, b)
+    HsvValue,
+}
+
+impl Luminance {
+    pub fn gray(&self, r: f32, g: f32, b: f32) -> f32 {
+        match self {
+            Luminance::Average => (r + g + b) / 3.0,
+            Luminance::Rec601 => 0.299 * r + 0.587 * g + 0.114 * b,
+            Luminance::Rec709 => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+            Luminance::Rec709Linear => {
+                0.2126 * srgb_to_linear(r) + 0.7152 * srgb_to_linear(g) + 0.0722 * srgb_to_linear(b)
+            }
+            Luminance::LabL => rgb_to_lab(r, g, b).l / 100.0,
+            Luminance::HsvValue => r.max(g).max(b),
         }
-        output.last_mut().unwrap().push(gray);
+    }
+
+    // linear light of a gray value from this method, for measures in stops. Only Rec709Linear
+    // is linear already. rgb_to_lab works on the encoded values, so undoing L* (as in lab_to_rgb)
+    // gives an sRGB encoded value like the other methods
+    pub fn to_linear(self, v: f32) -> f32 {
+        match self {
+            Luminance::Rec709Linear => v,
+            Luminance::LabL => {
+                let l = v * 100.0;
+                srgb_to_linear(if l > 7.9996248 { ((l + 16.0) / 116.0).powi(3) } else { l / 903.3 })
+            }
+            _ => srgb_to_linear(v),
+        }
+    }
+
+    pub const ALL: [Luminance; 6] = [
+        Luminance::Average,
+        Luminance::Rec601,
+        Luminance::Rec709,
+        Luminance::Rec709Linear,
+        Luminance::LabL,
+        Luminance::HsvValue,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Luminance> {
+        Luminance::ALL.iter().copied().find(|method| method.name() == name)
+    }
+
+    // name used when the method is written next to the features
+    pub fn name(&self) -> &'static str {
+        match self {
+            Luminance::Average => "average",
+            Luminance::Rec601 => "rec601",
+            Luminance::Rec709 => "rec709",
+            Luminance::Rec709Linear => "rec709_linear",
+            Luminance::LabL => "lab_l",
+            Luminance::HsvValue => "hsv_value",
+        }
+    }
+}
+
+// rows are converted in parallel, with simd where available
+pub fn grayscale(image: &Rgb32FImage, method: Luminance) -> Plane<f32> {
+    let width = image.width() as usize;
+    let mut output = Plane::new(width, image.height() as usize);
+    if width > 0 {
+        let stride = output.stride();
+        output.as_mut_slice()
+            .par_chunks_mut(stride)
+            .zip(image.as_raw().par_chunks_exact(width * 3))
+            .for_each(|(out, rgb)| gray_row(rgb, &mut out[..width], method));
     }
     output
 }
 
-pub fn grayscale_sd(image: Vec<Vec<f32>>) -> f32 {
-    std_dev_2d_vec(&image)
+// a plane from grayscale() as an 8-bit image, for the metrics that take a GrayImage
+pub fn quantize_gray(gray: &Plane<f32>) -> GrayImage {
+    let data = gray.values().map(|v| to_8bit(*v)).collect();
+    GrayImage::from_raw(gray.width() as u32, gray.height() as u32, data).unwrap()
+}
+
+// despite the name this is the RMS of the gray levels, see tonal::std_dev for the real deviation.
+// With a mask only the included pixels count
+pub fn grayscale_sd(image: &Plane<f32>, mask: Option<&Mask>) -> f32 {
+    if mask.is_none() {
+        return std_dev_plane(image);
+    }
+    let mut sum = 0.0f32;
+    let mut count = 0usize;
+    for (y, row) in image.rows().enumerate() {
+        for (x, v) in row.iter().enumerate() {
+            if included(mask, x as u32, y as u32) {
+                sum += v.powi(2);
+                count += 1;
+            }
+        }
+    }
+    if count == 0 {
+        return 0.0;
+    }
+    (sum / count as f32).sqrt()
 }
 
 pub fn posterize(image: &RgbImage, levels: u8) -> RgbImage {
@@ -201,10 +327,55 @@ pub fn posterize(image: &RgbImage, levels: u8) -> RgbImage {
     output
 }
 
-pub fn count_unique_colors(image: &RgbImage) -> usize {
+pub fn count_unique_colors(image: &RgbImage, mask: Option<&Mask>) -> usize {
     let mut colors = HashSet::new();
-    for pixel in image.pixels() {
-        colors.insert(pixel);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if included(mask, x, y) {
+            colors.insert(pixel);
+        }
     }
     colors.len()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::linear_to_srgb;
+
+    #[test]
+    fn every_method_maps_black_and_white_to_the_ends() {
+        for method in Luminance::ALL {
+            assert!(method.gray(0.0, 0.0, 0.0).abs() < 1e-6, "{}", method.name());
+            assert!((method.gray(1.0, 1.0, 1.0) - 1.0).abs() < 1e-4, "{}", method.name());
+        }
+    }
+
+    #[test]
+    fn pure_red_uses_the_method_weights() {
+        assert!((Luminance::Average.gray(1.0, 0.0, 0.0) - 1.0 / 3.0).abs() < 1e-6);
+        assert!((Luminance::Rec601.gray(1.0, 0.0, 0.0) - 0.299).abs() < 1e-6);
+        assert!((Luminance::Rec709.gray(1.0, 0.0, 0.0) - 0.2126).abs() < 1e-6);
+        assert!((Luminance::HsvValue.gray(1.0, 0.0, 0.0) - 1.0).abs() < 1e-6);
+        // linearizing only matters between the ends
+        assert!((Luminance::Rec709Linear.gray(0.5, 0.5, 0.5) - 0.21404).abs() < 1e-4);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for method in Luminance::ALL {
+            assert_eq!(Luminance::from_name(method.name()), Some(method));
+        }
+        assert_eq!(Luminance::from_name("luma"), None);
+    }
+
+    #[test]
+    fn to_linear_undoes_each_encoding() {
+        for linear in [0.001f32, 0.05, 0.18, 0.5, 1.0] {
+            let gray = Rgb32FImage::from_pixel(1, 1, image::Rgb([linear_to_srgb(linear); 3]));
+            for method in Luminance::ALL {
+                let value = grayscale(&gray, method)[(0, 0)];
+                assert!((method.to_linear(value) - linear).abs() < 1e-3, "{} at {}", method.name(), linear);
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use image::{ColorType, ImageEncoder, RgbImage};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+
+// COMPRESSION BASED VISUAL COMPLEXITY
+// the harder an image is to compress the more visually complex it is, see e.g.
+// P. Machado et al., "Computerized measures of visual complexity", Acta Psychologica 2015
+// everything is encoded in memory, nothing touches the disk
+
+// quality used for the jpeg complexity unless told otherwise
+pub const DEFAULT_JPEG_QUALITY: u8 = 75;
+
+pub struct CompressionComplexity {
+    // compressed size in bits divided by the number of pixels
+    pub png_bits_per_pixel: f32,
+    pub jpeg_bits_per_pixel: f32,
+    // compressed size divided by the raw 24 bit size, 1 means no compression at all
+    pub png_ratio: f32,
+    pub jpeg_ratio: f32,
+}
+
+pub fn png_size(image: &RgbImage) -> usize {
+    let mut buffer = Vec::new();
+    PngEncoder::new(&mut buffer)
+        .write_image(image.as_raw(), image.width(), image.height(), ColorType::Rgb8)
+        .unwrap();
+    buffer.len()
+}
+
+pub fn jpeg_size(image: &RgbImage, quality: u8) -> usize {
+    let mut buffer = Vec::new();
+    JpegEncoder::new_with_quality(&mut buffer, quality)
+        .write_image(image.as_raw(), image.width(), image.height(), ColorType::Rgb8)
+        .unwrap();
+    buffer.len()
+}
+
+pub fn png_bits_per_pixel(image: &RgbImage) -> f32 {
+    (png_size(image) * 8) as f32 / (image.width() * image.height()) as f32
+}
+
+pub fn jpeg_bits_per_pixel(image: &RgbImage, quality: u8) -> f32 {
+    (jpeg_size(image, quality) * 8) as f32 / (image.width() * image.height()) as f32
+}
+
+pub fn compression_complexity(image: &RgbImage, jpeg_quality: u8) -> CompressionComplexity {
+    let png_bits_per_pixel = png_bits_per_pixel(image);
+    let jpeg_bits_per_pixel = jpeg_bits_per_pixel(image, jpeg_quality);
+    CompressionComplexity {
+        png_bits_per_pixel,
+        jpeg_bits_per_pixel,
+        png_ratio: png_bits_per_pixel / 24.0,
+        jpeg_ratio: jpeg_bits_per_pixel / 24.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Lcg;
+
+    fn noise_image(width: u32, height: u32) -> RgbImage {
+        let mut rng = Lcg(1);
+        RgbImage::from_fn(width, height, |_, _| image::Rgb([rng.byte(), rng.byte(), rng.byte()]))
+    }
+
+    #[test]
+    fn noise_does_not_compress() {
+        let complexity = compression_complexity(&noise_image(128, 128), DEFAULT_JPEG_QUALITY);
+        // deflate can not shrink random bytes, only the headers and filter bytes are added
+        assert!(complexity.png_ratio > 0.99 && complexity.png_ratio < 1.05, "png ratio {}", complexity.png_ratio);
+        assert_eq!(complexity.png_ratio, complexity.png_bits_per_pixel / 24.0);
+    }
+
+    #[test]
+    fn constant_image_compresses_to_almost_nothing() {
+        let flat = RgbImage::from_pixel(128, 128, image::Rgb([40, 120, 200]));
+        let complexity = compression_complexity(&flat, DEFAULT_JPEG_QUALITY);
+        assert!(complexity.png_bits_per_pixel < 0.5, "png {}", complexity.png_bits_per_pixel);
+        assert!(complexity.jpeg_bits_per_pixel < 1.0, "jpeg {}", complexity.jpeg_bits_per_pixel);
+
+        let noise = compression_complexity(&noise_image(128, 128), DEFAULT_JPEG_QUALITY);
+        assert!(noise.jpeg_bits_per_pixel > 10.0 * complexity.jpeg_bits_per_pixel);
+    }
+}
@@ -0,0 +1,150 @@
+use image::GrayImage;
+use crate::plane::{gray_to_plane, Plane};
+
+// COMPOSITION
+// all features are computed from a non negative weight map, usually a
+// saliency map or an edge map. Positions are in [0, 1] image coordinates so images of
+// different sizes and aspect ratios are comparable
+
+const THIRDS: [f32; 2] = [1.0 / 3.0, 2.0 / 3.0];
+
+pub struct CompositionFeatures {
+    // weighted mean distance of the mass to the closest rule-of-thirds line
+    pub thirds_line_distance: f32,
+    // weighted mean distance of the mass to the closest power point (intersection of thirds lines)
+    pub power_point_distance: f32,
+    // distance between the center of mass and the closest power point
+    pub centroid_power_point_distance: f32,
+    // (left - right) / (left + right), positive when the left half is heavier
+    pub horizontal_balance: f32,
+    // (top - bottom) / (top + bottom), positive when the top half is heavier
+    pub vertical_balance: f32,
+    // center of mass relative to the image center, (x, y) in [-0.5, 0.5]
+    pub center_offset: (f32, f32),
+    // length of center_offset
+    pub center_offset_distance: f32,
+}
+
+// edge map (e.g. canny output) as a weight map, white pixels weigh 1
+pub fn edge_weights(edges: &GrayImage) -> Plane<f32> {
+    gray_to_plane(edges).map(|v| v / 255.0)
+}
+
+fn distance_to_thirds_line(x: f32, y: f32) -> f32 {
+    THIRDS.iter()
+        .map(|t| (x - t).abs().min((y - t).abs()))
+        .fold(f32::INFINITY, f32::min)
+}
+
+fn distance_to_power_point(x: f32, y: f32) -> f32 {
+    let mut best = f32::INFINITY;
+    for px in THIRDS {
+        for py in THIRDS {
+            best = best.min(((x - px).powi(2) + (y - py).powi(2)).sqrt());
+        }
+    }
+    best
+}
+
+pub fn composition_features(weights: &Plane<f32>) -> CompositionFeatures {
+    let (width, height) = (weights.width(), weights.height());
+
+    let mut total = 0.0f64;
+    let mut sum_x = 0.0f64;
+    let mut sum_y = 0.0f64;
+    let mut thirds = 0.0f64;
+    let mut power_points = 0.0f64;
+    let (mut left, mut right, mut top, mut bottom) = (0.0f64, 0.0f64, 0.0f64, 0.0f64);
+
+    for (y, row) in weights.rows().enumerate() {
+        // pixel centers
+        let ny = (y as f32 + 0.5) / height as f32;
+        for (x, weight) in row.iter().enumerate() {
+            let w = weight.max(0.0) as f64;
+            if w == 0.0 {
+                continue;
+            }
+            let nx = (x as f32 + 0.5) / width as f32;
+
+            total += w;
+            sum_x += w * nx as f64;
+            sum_y += w * ny as f64;
+            thirds += w * distance_to_thirds_line(nx, ny) as f64;
+            power_points += w * distance_to_power_point(nx, ny) as f64;
+
+            if nx < 0.5 { left += w } else { right += w }
+            if ny < 0.5 { top += w } else { bottom += w }
+        }
+    }
+
+    // an empty map has no composition to speak of, report a perfectly centered, balanced image
+    if total == 0.0 {
+        return CompositionFeatures {
+            thirds_line_distance: 0.0,
+            power_point_distance: 0.0,
+            centroid_power_point_distance: distance_to_power_point(0.5, 0.5),
+            horizontal_balance: 0.0,
+            vertical_balance: 0.0,
+            center_offset: (0.0, 0.0),
+            center_offset_distance: 0.0,
+        };
+    }
+
+    let centroid = ((sum_x / total) as f32, (sum_y / total) as f32);
+    let center_offset = (centroid.0 - 0.5, centroid.1 - 0.5);
+
+    CompositionFeatures {
+        thirds_line_distance: (thirds / total) as f32,
+        power_point_distance: (power_points / total) as f32,
+        centroid_power_point_distance: distance_to_power_point(centroid.0, centroid.1),
+        horizontal_balance: ((left - right) / total) as f32,
+        vertical_balance: ((top - bottom) / total) as f32,
+        center_offset,
+        center_offset_distance: (center_offset.0.powi(2) + center_offset.1.powi(2)).sqrt(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 300x300 map with weight 1 at the given pixels
+    fn point_map(points: &[(usize, usize)]) -> Plane<f32> {
+        Plane::from_fn(300, 300, |x, y| if points.contains(&(x, y)) { 1.0 } else { 0.0 })
+    }
+
+    #[test]
+    fn mass_on_a_power_point() {
+        // pixel center (99.5 / 300) is within 0.002 of the upper left power point
+        let features = composition_features(&point_map(&[(99, 99)]));
+        assert!(features.power_point_distance < 0.005);
+        assert!(features.thirds_line_distance < 0.005);
+        assert!(features.centroid_power_point_distance < 0.005);
+        assert_eq!(features.horizontal_balance, 1.0);
+        assert_eq!(features.vertical_balance, 1.0);
+        assert!((features.center_offset.0 + 1.0 / 6.0).abs() < 0.005);
+        assert!((features.center_offset_distance - (2.0f32).sqrt() / 6.0).abs() < 0.005);
+    }
+
+    #[test]
+    fn mirrored_masses_balance() {
+        let features = composition_features(&point_map(&[(30, 150), (269, 150)]));
+        assert_eq!(features.horizontal_balance, 0.0);
+        assert!(features.center_offset.0.abs() < 1e-6);
+        // the centroid is (within half a pixel) the center, sqrt(2) / 6 from every power point
+        assert!((features.centroid_power_point_distance - (2.0f32).sqrt() / 6.0).abs() < 0.005);
+    }
+
+    #[test]
+    fn empty_map_is_centered() {
+        let features = composition_features(&Plane::new(10, 10));
+        assert_eq!(features.center_offset, (0.0, 0.0));
+        assert_eq!(features.horizontal_balance, 0.0);
+    }
+
+    #[test]
+    fn edge_pixels_weigh_one() {
+        let edges = GrayImage::from_fn(2, 1, |x, _| image::Luma([if x == 0 { 255 } else { 0 }]));
+        assert_eq!(edge_weights(&edges), Plane::from_vec(2, 1, vec![1.0, 0.0]));
+    }
+}
@@ -0,0 +1,194 @@
+use std::cell::{OnceCell, RefCell};
+use std::time::{Duration, Instant};
+use image::{DynamicImage, GrayImage, Rgb32FImage, RgbImage};
+use crate::colorfulness::{grayscale, quantize_gray, rgb_to_lab_image, LabPixel, Luminance};
+use crate::metrics::{CANNY_HIGH, CANNY_LOW};
+use crate::plane::{gray_into_plane, gray_to_plane, Plane};
+use crate::simd::convolve_3x3;
+use crate::utils::{SOBEL_X, SOBEL_Y};
+
+// SHARED INTERMEDIATES
+// the representations metrics are computed on (gray planes, cieLAB, gradients, edges, ...) are
+// built on first use and kept for the other metrics of the same image. Every build is recorded
+// so a run can report what was computed and how long it took.
+// With full precision (16-bit and float sources) the gray levels the noise estimators and the
+// spatial information work on are not rounded to 8 bits first. Everything built from the 8-bit
+// gray image (coarseness, directionality, entropy, blockiness, the spectrum and the canny edges
+// behind edge density and fractal dimension) still sees 8-bit levels
+
+// sobel gradients of the gray levels, only where the kernel fits so both planes are
+// (width - 2) x (height - 2)
+pub struct Gradients {
+    pub x: Plane<f32>,
+    pub y: Plane<f32>,
+}
+
+pub struct BuiltIntermediate {
+    pub name: &'static str,
+    // build time of this intermediate alone, the ones it is derived from are recorded separately
+    pub duration: Duration,
+}
+
+pub struct AnalysisContext<'a> {
+    image: &'a Rgb32FImage,
+    luminance: Luminance,
+    full_precision: bool,
+    gray: OnceCell<Plane<f32>>,
+    gray_image: OnceCell<GrayImage>,
+    gray_u8: OnceCell<Plane<u8>>,
+    gray_levels: OnceCell<Plane<f32>>,
+    lab: OnceCell<Plane<LabPixel>>,
+    rgb8: OnceCell<RgbImage>,
+    gradients: OnceCell<Gradients>,
+    edges: OnceCell<GrayImage>,
+    built: RefCell<Vec<BuiltIntermediate>>,
+}
+
+impl<'a> AnalysisContext<'a> {
+    pub fn new(image: &'a Rgb32FImage, luminance: Luminance) -> AnalysisContext<'a> {
+        AnalysisContext::with_precision(image, luminance, false)
+    }
+
+    pub fn with_precision(image: &'a Rgb32FImage, luminance: Luminance, full_precision: bool) -> AnalysisContext<'a> {
+        AnalysisContext {
+            image,
+            luminance,
+            full_precision,
+            gray: OnceCell::new(),
+            gray_image: OnceCell::new(),
+            gray_u8: OnceCell::new(),
+            gray_levels: OnceCell::new(),
+            lab: OnceCell::new(),
+            rgb8: OnceCell::new(),
+            gradients: OnceCell::new(),
+            edges: OnceCell::new(),
+            built: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn image(&self) -> &'a Rgb32FImage {
+        self.image
+    }
+
+    pub fn luminance(&self) -> Luminance {
+        self.luminance
+    }
+
+    pub fn full_precision(&self) -> bool {
+        self.full_precision
+    }
+
+    // the inputs of build are requested before calling this, so the recorded time is only the
+    // work of this intermediate
+    fn cached<'c, T>(&self, cell: &'c OnceCell<T>, name: &'static str, build: impl FnOnce() -> T) -> &'c T {
+        cell.get_or_init(|| {
+            let start = Instant::now();
+            let value = build();
+            self.built.borrow_mut().push(BuiltIntermediate { name, duration: start.elapsed() });
+            value
+        })
+    }
+
+    // gray values in [0, 1], see grayscale()
+    pub fn gray(&self) -> &Plane<f32> {
+        self.cached(&self.gray, "gray", || grayscale(self.image, self.luminance))
+    }
+
+    // 8-bit version of gray(), see quantize_gray()
+    pub fn gray_image(&self) -> &GrayImage {
+        if let Some(gray_image) = self.gray_image.get() {
+            return gray_image;
+        }
+        let gray = self.gray();
+        self.cached(&self.gray_image, "gray_image", || quantize_gray(gray))
+    }
+
+    // the 8-bit gray image as a plane, for coarseness and directionality
+    pub fn gray_u8(&self) -> &Plane<u8> {
+        if let Some(gray_u8) = self.gray_u8.get() {
+            return gray_u8;
+        }
+        let gray_image = self.gray_image();
+        self.cached(&self.gray_u8, "gray_u8", || gray_into_plane(gray_image.clone()))
+    }
+
+    // gray levels as 0..255 floats for the noise estimators and gradients, rounded to whole
+    // levels like the 8-bit gray image unless the context has full precision
+    pub fn gray_levels(&self) -> &Plane<f32> {
+        if let Some(gray_levels) = self.gray_levels.get() {
+            return gray_levels;
+        }
+        if self.full_precision {
+            let gray = self.gray();
+            return self.cached(&self.gray_levels, "gray_levels", || gray.map(|v| v.clamp(0.0, 1.0) * 255.0));
+        }
+        let gray_image = self.gray_image();
+        self.cached(&self.gray_levels, "gray_levels", || gray_to_plane(gray_image))
+    }
+
+    pub fn lab(&self) -> &Plane<LabPixel> {
+        self.cached(&self.lab, "lab", || rgb_to_lab_image(self.image))
+    }
+
+    pub fn rgb8(&self) -> &RgbImage {
+        self.cached(&self.rgb8, "rgb8", || DynamicImage::ImageRgb32F(self.image.clone()).to_rgb8())
+    }
+
+    pub fn gradients(&self) -> &Gradients {
+        if let Some(gradients) = self.gradients.get() {
+            return gradients;
+        }
+        let gray_levels = self.gray_levels();
+        self.cached(&self.gradients, "gradients", || Gradients {
+            x: convolve_3x3(gray_levels, &SOBEL_X),
+            y: convolve_3x3(gray_levels, &SOBEL_Y),
+        })
+    }
+
+    // canny edges with the thresholds of the edge density metric
+    pub fn edges(&self) -> &GrayImage {
+        if let Some(edges) = self.edges.get() {
+            return edges;
+        }
+        let gray_image = self.gray_image();
+        self.cached(&self.edges, "edges", || imageproc::edges::canny(gray_image, CANNY_LOW, CANNY_HIGH))
+    }
+
+    // every intermediate built so far, in build order
+    pub fn built(&self) -> std::cell::Ref<'_, Vec<BuiltIntermediate>> {
+        self.built.borrow()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metric;
+    use crate::utils::Lcg;
+
+    #[test]
+    fn shared_context_gives_the_same_values() {
+        let mut rng = Lcg(1);
+        let image = Rgb32FImage::from_fn(40, 30, |x, y| {
+            let noise = rng.unit();
+            image::Rgb([x as f32 / 40.0, 0.5 * noise + 0.25, if (x / 8 + y / 8) % 2 == 0 { 0.2 } else { 0.8 }])
+        });
+
+        let context = AnalysisContext::new(&image, Luminance::Rec709);
+        for metric in Metric::ALL {
+            let fresh = metric.compute_with(&AnalysisContext::new(&image, Luminance::Rec709), None);
+            assert_eq!(metric.compute_with(&context, None).to_bits(), fresh.to_bits(), "{}", metric.name());
+        }
+
+        // every intermediate is built once, in the order the metrics first asked for it
+        let names: Vec<&str> = context.built().iter().map(|built| built.name).collect();
+        assert_eq!(names, vec!["gray", "gray_image", "gray_u8", "lab", "rgb8", "edges", "gray_levels", "gradients"]);
+    }
+
+    #[test]
+    fn full_precision_keeps_fractional_gray_levels() {
+        let image = Rgb32FImage::from_pixel(4, 4, image::Rgb([0.5, 0.5, 0.5]));
+        assert_eq!(AnalysisContext::new(&image, Luminance::Average).gray_levels()[(0, 0)], 128.0);
+        assert_eq!(AnalysisContext::with_precision(&image, Luminance::Average, true).gray_levels()[(0, 0)], 127.5);
+    }
+}
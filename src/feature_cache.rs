@@ -0,0 +1,343 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::colorfulness::Luminance;
+use crate::loader::LoadOptions;
+use crate::metrics::Metric;
+use crate::preprocess::ResizePolicy;
+
+// PERSISTENT FEATURE CACHE
+// metric values of earlier batch runs, keyed by the content of the image file, the metric and a
+// hash of everything the value depends on (metric constants, luminance, resize policy, EXIF
+// orientation and color management). Renamed or moved files are still found, edited files or
+// changed settings are computed again.
+// The store is a tab separated text file that is only appended to during runs, the last line of a
+// key wins. Prune and invalidate rewrite it without the dropped lines
+
+// bump when a metric implementation changes its results, every older entry then misses
+const CACHE_VERSION: u32 = 1;
+
+const HEADER: &str = "# content_hash\tmetric\tparameters\toriginal_width\toriginal_height\tanalysis_width\tanalysis_height\tvalue\tpath";
+
+pub const DEFAULT_CACHE_FILE: &str = "res/cache/features.tsv";
+
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    pub content_hash: u64,
+    pub metric: String,
+    pub parameters: u64,
+    pub original: (u32, u32),
+    pub analysis: (u32, u32),
+    pub value: f32,
+    // the file the value was computed from, for inspecting and pruning
+    pub path: PathBuf,
+}
+
+impl CacheEntry {
+    // None when the path holds a tab or line break, which would split the entry. Such files are
+    // simply not cached
+    fn line(&self) -> Option<String> {
+        let path = self.path.display().to_string();
+        if path.contains(['\t', '\n', '\r']) {
+            return None;
+        }
+        Some(format!(
+            "{:016x}\t{}\t{:016x}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.content_hash, self.metric, self.parameters, self.original.0, self.original.1,
+            self.analysis.0, self.analysis.1, self.value, path
+        ))
+    }
+
+    // None for lines that are not entries (header, damaged lines from an interrupted run)
+    fn parse(line: &str) -> Option<CacheEntry> {
+        let fields: Vec<&str> = line.splitn(9, '\t').collect();
+        if fields.len() != 9 || line.starts_with('#') {
+            return None;
+        }
+        Some(CacheEntry {
+            content_hash: u64::from_str_radix(fields[0], 16).ok()?,
+            metric: fields[1].to_string(),
+            parameters: u64::from_str_radix(fields[2], 16).ok()?,
+            original: (fields[3].parse().ok()?, fields[4].parse().ok()?),
+            analysis: (fields[5].parse().ok()?, fields[6].parse().ok()?),
+            value: fields[7].parse().ok()?,
+            path: PathBuf::from(fields[8]),
+        })
+    }
+
+    fn key(&self) -> (u64, String, u64) {
+        (self.content_hash, self.metric.clone(), self.parameters)
+    }
+}
+
+pub struct CacheSummary {
+    pub entries: usize,
+    // distinct image contents
+    pub images: usize,
+    // metric name, entries, distinct parameter sets
+    pub metrics: Vec<(String, usize, usize)>,
+}
+
+pub struct FeatureCache {
+    file: PathBuf,
+    entries: HashMap<(u64, String, u64), CacheEntry>,
+}
+
+impl FeatureCache {
+    // a missing file is an empty cache, it is created with the first insert
+    pub fn open(file: &Path) -> io::Result<FeatureCache> {
+        let mut entries = HashMap::new();
+        match fs::read_to_string(file) {
+            Ok(contents) => {
+                for entry in contents.lines().filter_map(CacheEntry::parse) {
+                    entries.insert(entry.key(), entry);
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+        Ok(FeatureCache { file: file.to_path_buf(), entries })
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &CacheEntry> {
+        self.entries.values()
+    }
+
+    pub fn get(&self, content_hash: u64, metric: Metric, parameters: u64) -> Option<&CacheEntry> {
+        self.entries.get(&(content_hash, metric.name().to_string(), parameters))
+    }
+
+    // appends the entries to the file, replacing entries with the same key
+    pub fn insert(&mut self, new_entries: Vec<CacheEntry>) -> io::Result<()> {
+        if new_entries.is_empty() {
+            return Ok(());
+        }
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let is_new = !self.file.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.file)?;
+        let mut out = String::new();
+        if is_new {
+            out.push_str(HEADER);
+            out.push('\n');
+        }
+        for entry in new_entries {
+            if let Some(line) = entry.line() {
+                out.push_str(&line);
+                out.push('\n');
+                self.entries.insert(entry.key(), entry);
+            }
+        }
+        file.write_all(out.as_bytes())
+    }
+
+    // keeps the entries keep returns true for and rewrites the file, returns how many were dropped
+    pub fn retain(&mut self, mut keep: impl FnMut(&CacheEntry) -> bool) -> io::Result<usize> {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| keep(entry));
+
+        let mut lines: Vec<String> = self.entries.values().filter_map(|entry| entry.line()).collect();
+        lines.sort();
+        lines.insert(0, HEADER.to_string());
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // written next to the cache and renamed so an interrupted rewrite keeps the old file
+        let temporary = self.file.with_extension("tmp");
+        fs::write(&temporary, lines.join("\n") + "\n")?;
+        fs::rename(&temporary, &self.file)?;
+        Ok(before - self.entries.len())
+    }
+
+    pub fn summary(&self) -> CacheSummary {
+        let images: HashSet<u64> = self.entries().map(|entry| entry.content_hash).collect();
+        let mut per_metric: HashMap<&str, (usize, HashSet<u64>)> = HashMap::new();
+        for entry in self.entries() {
+            let (count, parameters) = per_metric.entry(&entry.metric).or_default();
+            *count += 1;
+            parameters.insert(entry.parameters);
+        }
+        let mut metrics: Vec<(String, usize, usize)> = per_metric.into_iter()
+            .map(|(metric, (count, parameters))| (metric.to_string(), count, parameters.len()))
+            .collect();
+        metrics.sort();
+        CacheSummary { entries: self.len(), images: images.len(), metrics }
+    }
+
+    // drops the entries of files that were deleted or changed since they were analyzed and of
+    // metrics this build does not know anymore
+    pub fn prune(&mut self) -> io::Result<usize> {
+        let mut current: HashMap<PathBuf, Option<u64>> = HashMap::new();
+        self.retain(|entry| {
+            if Metric::from_name(&entry.metric).is_none() {
+                return false;
+            }
+            let hash = current.entry(entry.path.clone())
+                .or_insert_with(|| fs::read(&entry.path).ok().map(|bytes| content_hash(&bytes)));
+            *hash == Some(entry.content_hash)
+        })
+    }
+
+    // drops the entries of the given metrics (every metric when empty), with an image only the
+    // ones of that file (by its current content or the recorded path)
+    pub fn invalidate(&mut self, metrics: &[Metric], image: Option<&Path>) -> io::Result<usize> {
+        let image_hash = image.and_then(|path| fs::read(path).ok()).map(|bytes| content_hash(&bytes));
+        self.retain(|entry| {
+            let metric_matches = metrics.is_empty() || metrics.iter().any(|metric| metric.name() == entry.metric);
+            let image_matches = image.is_none_or(|path| entry.path == path || image_hash == Some(entry.content_hash));
+            !(metric_matches && image_matches)
+        })
+    }
+}
+
+// 64 bit FNV-1a, see http://www.isthe.com/chongo/tech/comp/fnv/
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// hash of the encoded file, with the length mixed in so a collision also needs the same size
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    fnv1a(bytes) ^ (bytes.len() as u64).rotate_left(32)
+}
+
+// everything besides the pixels a metric value depends on. The tone mapping only changes scene
+// linear input (see hdr::is_scene_linear), switching it keeps the values of 8 and 16 bit files
+pub fn parameter_hash(metric: Metric, luminance: Luminance, policy: &ResizePolicy, load: &LoadOptions, scene_linear: bool) -> u64 {
+    let tone_mapping = if scene_linear { format!("{:?}", policy.tone_mapping) } else { String::new() };
+    let description = format!(
+        "v{}|{}|{}|{}|{:?}|{:?}|{:?}|{}|{}|{}",
+        CACHE_VERSION, metric.name(), metric.parameters(), luminance.name(),
+        policy.size, policy.filter, policy.framing, tone_mapping,
+        load.apply_orientation, load.color_management
+    );
+    fnv1a(description.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(content_hash: u64, metric: Metric, value: f32, path: &str) -> CacheEntry {
+        CacheEntry {
+            content_hash,
+            metric: metric.name().to_string(),
+            parameters: 7,
+            original: (640, 480),
+            analysis: (320, 240),
+            value,
+            path: PathBuf::from(path),
+        }
+    }
+
+    // a cache file of its own per test, tests run in parallel
+    fn temporary_cache(name: &str) -> PathBuf {
+        let file = std::env::temp_dir().join(format!("feature_cache_{}_{}.tsv", name, std::process::id()));
+        let _ = fs::remove_file(&file);
+        file
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        for value in [0.25, -3.5e-7, f32::MAX, f32::INFINITY, f32::NEG_INFINITY] {
+            let original = entry(0xdeadbeef, Metric::Entropy, value, "res/some image.jpg");
+            let parsed = CacheEntry::parse(&original.line().unwrap()).unwrap();
+            assert_eq!(parsed.value, value);
+            assert_eq!(parsed.key(), original.key());
+            assert_eq!((parsed.original, parsed.analysis, parsed.path), (original.original, original.analysis, original.path));
+        }
+        let nan = entry(1, Metric::Entropy, f32::NAN, "a.png");
+        assert!(CacheEntry::parse(&nan.line().unwrap()).unwrap().value.is_nan());
+    }
+
+    #[test]
+    fn paths_with_separators_are_not_stored() {
+        for path in ["a\tb.png", "a\nb.png"] {
+            assert!(entry(1, Metric::Entropy, 1.0, path).line().is_none());
+        }
+
+        let file = temporary_cache("separators");
+        let mut cache = FeatureCache::open(&file).unwrap();
+        cache.insert(vec![entry(1, Metric::Entropy, 1.0, "a\nb.png"), entry(2, Metric::Entropy, 2.0, "c.png")]).unwrap();
+        let reopened = FeatureCache::open(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!((cache.len(), reopened.len()), (1, 1));
+        assert_eq!(reopened.get(2, Metric::Entropy, 7).unwrap().value, 2.0);
+    }
+
+    #[test]
+    fn damaged_lines_are_skipped() {
+        let good = entry(1, Metric::Entropy, 4.5, "a.png").line().unwrap();
+        let truncated = &good[..good.len() - 12];
+        let contents = format!("{}\n{}\n{}\nnot an entry\n\t\t\t\t\t\t\t\t\n{}", HEADER, good, truncated, &good[..20]);
+
+        let file = temporary_cache("damaged");
+        fs::write(&file, contents).unwrap();
+        let cache = FeatureCache::open(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(1, Metric::Entropy, 7).unwrap().value, 4.5);
+        assert!(CacheEntry::parse(HEADER).is_none());
+    }
+
+    #[test]
+    fn retain_and_invalidate_keep_the_other_entries() {
+        let file = temporary_cache("invalidate");
+        let mut cache = FeatureCache::open(&file).unwrap();
+        assert!(cache.is_empty());
+        cache.insert(vec![
+            entry(1, Metric::Entropy, 1.0, "a.png"),
+            entry(1, Metric::GrayscaleSd, 2.0, "a.png"),
+            entry(2, Metric::Entropy, 3.0, "b.png"),
+            entry(2, Metric::GrayscaleSd, 4.0, "b.png"),
+        ]).unwrap();
+        // the last line of a key wins
+        cache.insert(vec![entry(2, Metric::GrayscaleSd, 5.0, "b.png")]).unwrap();
+
+        assert_eq!(cache.invalidate(&[Metric::Entropy], Some(Path::new("a.png"))).unwrap(), 1);
+        assert_eq!(cache.retain(|entry| entry.value != 3.0).unwrap(), 1);
+
+        let reopened = FeatureCache::open(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+        let mut values: Vec<f32> = reopened.entries().map(|entry| entry.value).collect();
+        values.sort_by(f32::total_cmp);
+        assert_eq!(values, vec![2.0, 5.0]);
+        assert!(reopened.get(1, Metric::Entropy, 7).is_none());
+    }
+
+    #[test]
+    fn content_hash_depends_on_every_byte() {
+        assert_eq!(content_hash(b"image"), content_hash(b"image"));
+        assert_ne!(content_hash(b"image"), content_hash(b"imagf"));
+        assert_ne!(content_hash(b""), content_hash(b"\0"));
+    }
+
+    #[test]
+    fn tone_mapping_only_keys_scene_linear_files() {
+        let load = LoadOptions::default();
+        let reinhard = ResizePolicy::default();
+        let clip = ResizePolicy { tone_mapping: crate::hdr::ToneMapping::Clip, ..ResizePolicy::default() };
+        let hash = |policy: &ResizePolicy, scene_linear| parameter_hash(Metric::Entropy, Luminance::Rec709, policy, &load, scene_linear);
+        assert_eq!(hash(&reinhard, false), hash(&clip, false));
+        assert_ne!(hash(&reinhard, true), hash(&clip, true));
+        assert_ne!(hash(&reinhard, false), hash(&reinhard, true));
+    }
+}
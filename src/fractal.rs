@@ -0,0 +1,145 @@
+use image::GrayImage;
+use crate::plane::Plane;
+use crate::utils::linear_regression;
+
+// FRACTAL DIMENSION
+
+// smallest box used by the differential box counting, 1 pixel boxes have no gray level spread
+const DBC_MIN_BOX: u32 = 2;
+const GRAY_LEVELS: f64 = 256.0;
+
+pub struct FractalDimension {
+    // slope of the log-log fit
+    pub dimension: f32,
+    // how straight the log-log plot is, a real fractal gives a value close to 1
+    pub r_squared: f32,
+    // (box size in pixels, number of boxes) for every scale that went into the fit
+    pub counts: Vec<(u32, u64)>,
+}
+
+// edge map from canny (or any gray image), white pixels are foreground
+pub fn binary_from_gray(pixels: &GrayImage) -> Plane<bool> {
+    Plane::from_vec(pixels.width() as usize, pixels.height() as usize, pixels.pixels().map(|pix| pix[0] > 127).collect())
+}
+
+fn fit(counts: Vec<(u32, u64)>, reference: f64) -> FractalDimension {
+    // N(s) ~ (1 / s)^D, so D is the slope of log N against log(1 / s)
+    let used: Vec<&(u32, u64)> = counts.iter().filter(|(_, count)| *count > 0).collect();
+    let xs: Vec<f64> = used.iter().map(|(size, _)| (reference / *size as f64).ln()).collect();
+    let ys: Vec<f64> = used.iter().map(|(_, count)| (*count as f64).ln()).collect();
+    let (slope, _, r_squared) = linear_regression(&xs, &ys);
+
+    FractalDimension {
+        dimension: slope as f32,
+        r_squared: r_squared as f32,
+        counts,
+    }
+}
+
+// box counting dimension of a binary image over dyadic box sizes 1, 2, 4 ... up to half of the
+// shorter side. Boxes sticking out of the image at the right / bottom border are counted as well
+pub fn box_counting(binary: &Plane<bool>) -> FractalDimension {
+    let height = binary.height() as u32;
+    let width = binary.width() as u32;
+
+    let mut counts = Vec::new();
+    let mut size = 1u32;
+    while size <= (width.min(height) / 2).max(1) {
+        let columns = width.div_ceil(size) as usize;
+        let rows = height.div_ceil(size) as usize;
+        let mut occupied = vec![false; columns * rows];
+        for (y, row) in binary.rows().enumerate() {
+            for (x, foreground) in row.iter().enumerate() {
+                if *foreground {
+                    occupied[(y / size as usize) * columns + x / size as usize] = true;
+                }
+            }
+        }
+        counts.push((size, occupied.iter().filter(|o| **o).count() as u64));
+        size *= 2;
+    }
+
+    fit(counts, 1.0)
+}
+
+// see N. Sarkar, B. B. Chaudhuri, "An efficient differential box-counting approach to compute
+// fractal dimension of image", IEEE TSMC 1994
+// the image is seen as a surface with the gray level as height. Every s x s cell of the grid is
+// covered by a column of s x s x h boxes (h = s * G / M), the number of boxes needed to span
+// from the darkest to the brightest pixel of the cell is summed over the grid
+pub fn differential_box_counting(pixels: &GrayImage) -> FractalDimension {
+    let (width, height) = pixels.dimensions();
+    let m = width.min(height);
+
+    let mut counts = Vec::new();
+    let mut size = DBC_MIN_BOX;
+    while size <= m / 2 {
+        let box_height = size as f64 * GRAY_LEVELS / m as f64;
+        let mut total = 0u64;
+        // only full cells, partial ones at the border would bias the count
+        for cy in 0..(height / size) {
+            for cx in 0..(width / size) {
+                let mut min = u8::MAX;
+                let mut max = u8::MIN;
+                for y in (cy * size)..((cy + 1) * size) {
+                    for x in (cx * size)..((cx + 1) * size) {
+                        let v = pixels.get_pixel(x, y)[0];
+                        min = min.min(v);
+                        max = max.max(v);
+                    }
+                }
+                let l = (min as f64 / box_height).floor() as u64;
+                let k = (max as f64 / box_height).floor() as u64;
+                total += k - l + 1;
+            }
+        }
+        counts.push((size, total));
+        size *= 2;
+    }
+
+    fit(counts, m as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Lcg;
+
+    #[test]
+    fn line_has_dimension_one() {
+        let line = Plane::from_fn(64, 64, |_, y| y == 20);
+        let fractal = box_counting(&line);
+        assert!((fractal.dimension - 1.0).abs() < 1e-4, "dimension {}", fractal.dimension);
+        assert!(fractal.r_squared > 0.9999);
+        assert_eq!(fractal.counts.first(), Some(&(1, 64)));
+    }
+
+    #[test]
+    fn filled_square_has_dimension_two() {
+        let square = Plane::from_fn(64, 64, |_, _| true);
+        let fractal = box_counting(&square);
+        assert!((fractal.dimension - 2.0).abs() < 1e-4, "dimension {}", fractal.dimension);
+        assert_eq!(fractal.counts.last(), Some(&(32, 4)));
+    }
+
+    #[test]
+    fn binary_threshold_is_the_middle_gray() {
+        let gray = GrayImage::from_fn(2, 1, |x, _| image::Luma([127 + x as u8]));
+        assert_eq!(binary_from_gray(&gray), Plane::from_vec(2, 1, vec![false, true]));
+    }
+
+    #[test]
+    fn flat_surface_has_dimension_two() {
+        // a single box per cell at every scale, (M / s)^2 boxes
+        let fractal = differential_box_counting(&GrayImage::from_pixel(64, 64, image::Luma([100])));
+        assert!((fractal.dimension - 2.0).abs() < 1e-4, "dimension {}", fractal.dimension);
+    }
+
+    #[test]
+    fn rough_surface_is_above_two() {
+        let mut rng = Lcg(1);
+        let noise = GrayImage::from_fn(64, 64, |_, _| image::Luma([rng.byte()]));
+        let fractal = differential_box_counting(&noise);
+        assert!(fractal.dimension > 2.5 && fractal.dimension <= 3.0, "dimension {}", fractal.dimension);
+    }
+}
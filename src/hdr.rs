@@ -0,0 +1,247 @@
+use std::io::Cursor;
+use image::codecs::hdr::HdrDecoder;
+use image::codecs::openexr::OpenExrDecoder;
+use image::codecs::tiff::TiffDecoder;
+use image::io::Reader as ImageReader;
+use image::{ColorType, DynamicImage, ImageDecoder, ImageError, ImageFormat, Rgb32FImage, Rgba32FImage};
+use rayon::prelude::*;
+use crate::utils::linear_to_srgb;
+
+// HIGH DYNAMIC RANGE INPUT
+// 8 and 16 bit files are display referred, gamma encoded values in [0, 1] that to_rgb32f keeps
+// at full precision. Float images (Radiance .hdr, OpenEXR, float TIFF) hold scene linear light
+// without an upper bound, the display referred metrics (colorfulness, gray levels, edges, ...)
+// would see clipped, un-encoded values. They are tone mapped to sRGB first
+// see E. Reinhard, M. Stark, P. Shirley, J. Ferwerda, "Photographic Tone Reproduction for
+// Digital Images", SIGGRAPH 2002
+
+// the "key" of a normal scene, the log average luminance is mapped to it. Section 3.1
+const MIDDLE_GRAY: f32 = 0.18;
+// keeps black pixels out of the logarithm, equation 1
+const LOG_DELTA: f32 = 1e-6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapping {
+    // values above 1 are cut off, the exposure stays as stored in the file
+    Clip,
+    // scaled so the log average luminance becomes middle gray, shifted by stops, then clipped
+    Exposure { stops: f32 },
+    // the same scaling followed by the global operator of equation 4, the brightest pixel
+    // becomes white and highlights are compressed instead of clipped
+    Reinhard,
+}
+
+impl ToneMapping {
+    // clip, exposure, exposure:-1.5 or reinhard
+    pub fn parse(value: &str) -> Option<ToneMapping> {
+        match value.split_once(':') {
+            Some(("exposure", stops)) => Some(ToneMapping::Exposure { stops: stops.parse().ok()? }),
+            Some(_) => None,
+            None => match value {
+                "clip" => Some(ToneMapping::Clip),
+                "exposure" => Some(ToneMapping::Exposure { stops: 0.0 }),
+                "reinhard" => Some(ToneMapping::Reinhard),
+                _ => None,
+            },
+        }
+    }
+
+    // the form parse() reads
+    pub fn name(&self) -> String {
+        match self {
+            ToneMapping::Clip => "clip".to_string(),
+            ToneMapping::Exposure { stops } => format!("exposure:{}", stops),
+            ToneMapping::Reinhard => "reinhard".to_string(),
+        }
+    }
+}
+
+// decodes like image's Reader, except that Radiance files keep their float values. image's own
+// hdr path hands out an 8-bit image with everything above 1 clipped
+pub fn decode_image(bytes: &[u8]) -> Result<DynamicImage, ImageError> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    if reader.format() != Some(ImageFormat::Hdr) {
+        return reader.decode();
+    }
+    let decoder = HdrDecoder::new(Cursor::new(bytes))?;
+    let metadata = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?;
+    let samples = pixels.iter().flat_map(|pixel| pixel.0).collect();
+    Ok(DynamicImage::ImageRgb32F(Rgb32FImage::from_raw(metadata.width, metadata.height, samples).unwrap()))
+}
+
+// what the decoded samples are
+#[derive(Clone, Copy, Debug)]
+pub struct InputRange {
+    pub bits_per_channel: u16,
+    // float samples, tone mapped before analysis
+    pub scene_linear: bool,
+    // largest (linear) luminance, 1 for display referred input
+    pub peak: f32,
+}
+
+impl InputRange {
+    // more than 8 bits per channel, metrics can use the samples without 8-bit rounding
+    pub fn high_precision(&self) -> bool {
+        self.bits_per_channel > 8
+    }
+}
+
+// whether decode_image() gives float samples, from the file header alone. Lets the feature cache
+// tell scene linear files apart before decoding
+pub fn is_scene_linear(bytes: &[u8]) -> bool {
+    let color = match image::guess_format(bytes) {
+        Ok(ImageFormat::Hdr) => return true,
+        Ok(ImageFormat::OpenExr) => OpenExrDecoder::new(Cursor::new(bytes)).map(|decoder| decoder.color_type()),
+        Ok(ImageFormat::Tiff) => TiffDecoder::new(Cursor::new(bytes)).map(|decoder| decoder.color_type()),
+        _ => return false,
+    };
+    matches!(color, Ok(ColorType::Rgb32F | ColorType::Rgba32F))
+}
+
+fn luminance(rgb: &[f32]) -> f32 {
+    // Rec. 709 weights on linear values
+    (0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]).max(0.0)
+}
+
+pub fn input_range(image: &DynamicImage) -> InputRange {
+    let color = image.color();
+    let bits_per_channel = color.bits_per_pixel() / color.channel_count() as u16;
+    let (samples, channels) = match image {
+        DynamicImage::ImageRgb32F(image) => (image.as_raw(), 3),
+        DynamicImage::ImageRgba32F(image) => (image.as_raw(), 4),
+        _ => return InputRange { bits_per_channel, scene_linear: false, peak: 1.0 },
+    };
+    InputRange {
+        bits_per_channel,
+        scene_linear: true,
+        peak: samples.par_chunks_exact(channels).map(luminance).reduce(|| 0.0, f32::max),
+    }
+}
+
+// exp(mean(log(delta + L))), equation 1
+fn log_average_luminance(samples: &[f32], channels: usize) -> f32 {
+    let count = (samples.len() / channels).max(1);
+    let sum: f64 = samples.par_chunks_exact(channels)
+        .map(|pixel| ((LOG_DELTA + luminance(pixel)) as f64).ln())
+        .sum();
+    (sum / count as f64).exp() as f32
+}
+
+// maps linear samples (rgb or rgba) to sRGB encoded values in [0, 1] in place, alpha is kept
+fn tone_map_samples(samples: &mut [f32], channels: usize, mapping: ToneMapping) {
+    let scale = match mapping {
+        ToneMapping::Clip => 1.0,
+        ToneMapping::Exposure { stops } => MIDDLE_GRAY / log_average_luminance(samples, channels) * stops.exp2(),
+        ToneMapping::Reinhard => MIDDLE_GRAY / log_average_luminance(samples, channels),
+    };
+    // L_white, the smallest luminance mapped to pure white (equation 4), here the scaled peak
+    let white = samples.par_chunks_exact(channels).map(luminance).reduce(|| 0.0, f32::max) * scale;
+
+    samples.par_chunks_exact_mut(channels).for_each(|pixel| {
+        let factor = match mapping {
+            ToneMapping::Reinhard => {
+                let scaled = luminance(pixel) * scale;
+                let display = scaled * (1.0 + scaled / (white * white).max(LOG_DELTA)) / (1.0 + scaled);
+                // the color is scaled with its luminance so hue and saturation stay
+                if scaled > 0.0 { display / scaled * scale } else { 0.0 }
+            }
+            _ => scale,
+        };
+        for value in pixel.iter_mut().take(3) {
+            *value = linear_to_srgb((value.max(0.0) * factor).min(1.0));
+        }
+    });
+}
+
+// the display referred version of a float image, None for 8 and 16 bit images which are display
+// referred already
+pub fn tone_map(image: &DynamicImage, mapping: ToneMapping) -> Option<DynamicImage> {
+    match image {
+        DynamicImage::ImageRgb32F(image) => {
+            let mut samples = image.as_raw().clone();
+            tone_map_samples(&mut samples, 3, mapping);
+            Some(DynamicImage::ImageRgb32F(Rgb32FImage::from_raw(image.width(), image.height(), samples).unwrap()))
+        }
+        DynamicImage::ImageRgba32F(image) => {
+            let mut samples = image.as_raw().clone();
+            tone_map_samples(&mut samples, 4, mapping);
+            Some(DynamicImage::ImageRgba32F(Rgba32FImage::from_raw(image.width(), image.height(), samples).unwrap()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(image: &DynamicImage, format: image::ImageOutputFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), format).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn parse_tone_mappings() {
+        assert_eq!(ToneMapping::parse("clip"), Some(ToneMapping::Clip));
+        assert_eq!(ToneMapping::parse("exposure"), Some(ToneMapping::Exposure { stops: 0.0 }));
+        assert_eq!(ToneMapping::parse("exposure:-1.5"), Some(ToneMapping::Exposure { stops: -1.5 }));
+        assert_eq!(ToneMapping::parse("reinhard"), Some(ToneMapping::Reinhard));
+        assert_eq!(ToneMapping::parse("exposure:bright"), None);
+        assert_eq!(ToneMapping::parse("clip:1"), None);
+        assert_eq!(ToneMapping::parse("filmic"), None);
+        for name in ["clip", "exposure:0", "exposure:-1.5", "reinhard"] {
+            assert_eq!(ToneMapping::parse(name).unwrap().name(), name);
+        }
+    }
+
+    #[test]
+    fn reinhard_maps_the_peak_to_white() {
+        let image = Rgb32FImage::from_fn(4, 1, |x, _| {
+            let v = [0.05, 0.2, 1.0, 12.0][x as usize];
+            image::Rgb([v, v, v])
+        });
+        let input = input_range(&DynamicImage::ImageRgb32F(image.clone()));
+        assert!(input.scene_linear);
+        assert!((input.peak - 12.0).abs() < 1e-4);
+
+        let mapped = tone_map(&DynamicImage::ImageRgb32F(image), ToneMapping::Reinhard).unwrap().into_rgb32f();
+        assert!(mapped.get_pixel(3, 0).0.iter().all(|v| (v - 1.0).abs() < 1e-5));
+        // the darker pixels stay below white and keep their order
+        let levels: Vec<f32> = mapped.pixels().map(|pixel| pixel[0]).collect();
+        assert!(levels.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn clip_is_the_srgb_encode_of_the_clamped_value() {
+        let image = Rgba32FImage::from_fn(4, 1, |x, _| {
+            let v = [-0.5, 0.002, 0.4, 3.0][x as usize];
+            image::Rgba([v, v, v, 0.25])
+        });
+        let mapped = tone_map(&DynamicImage::ImageRgba32F(image.clone()), ToneMapping::Clip).unwrap().into_rgba32f();
+        for (source, pixel) in image.pixels().zip(mapped.pixels()) {
+            assert_eq!(pixel[0], linear_to_srgb(source[0].clamp(0.0, 1.0)));
+            assert_eq!(pixel[3], 0.25);
+        }
+    }
+
+    #[test]
+    fn display_referred_input_is_not_tone_mapped() {
+        let image = DynamicImage::ImageRgb16(image::ImageBuffer::from_pixel(2, 2, image::Rgb([1000u16, 2000, 3000])));
+        let input = input_range(&image);
+        assert!(!input.scene_linear && input.high_precision());
+        assert_eq!(input.peak, 1.0);
+        assert!(tone_map(&image, ToneMapping::Reinhard).is_none());
+    }
+
+    #[test]
+    fn scene_linear_files_are_told_apart_by_their_header() {
+        let float = DynamicImage::ImageRgba32F(Rgba32FImage::from_pixel(2, 2, image::Rgba([2.0, 1.0, 0.5, 1.0])));
+        let png = encoded(&DynamicImage::ImageRgb8(image::RgbImage::new(2, 2)), image::ImageOutputFormat::Png);
+        let exr = encoded(&float, image::ImageOutputFormat::OpenExr);
+        assert!(!is_scene_linear(&png));
+        assert!(is_scene_linear(&exr));
+        assert!(matches!(decode_image(&exr).unwrap(), DynamicImage::ImageRgba32F(_)));
+    }
+}
@@ -0,0 +1,371 @@
+use image::DynamicImage;
+use rayon::prelude::*;
+use crate::utils::{linear_to_srgb, to_8bit};
+
+// ICC COLOR MANAGEMENT
+// files tagged with a wide gamut profile (Display P3, Adobe RGB, ProPhoto, ...) hold different
+// colors for the same numbers than sRGB, which every metric assumes. Matrix/TRC profiles, the
+// kind all common RGB working spaces use, are converted here: the tone reproduction curves
+// linearize the samples, the colorant matrix takes them to the D50 profile connection space and
+// the inverse sRGB matrix (Bradford adapted to D50, as in the sRGB ICC profile) back to sRGB.
+// Colors outside the sRGB gamut are clipped. LUT based profiles are not converted
+// see ICC.1:2010 (profile version 4.3.0.0), sections 7 (profile structure) and 10 (tag types)
+
+// the colorants of sRGB relative to D50, rows X, Y, Z and columns r, g, b
+// see http://www.brucelindbloom.com/index.html?Eqn_RGB_XYZ_Matrix.html
+const SRGB_D50: [[f32; 3]; 3] = [
+    [0.4360747, 0.3850649, 0.1430804],
+    [0.2225045, 0.7168786, 0.0606169],
+    [0.0139322, 0.0971045, 0.7141733],
+];
+
+// a profile whose matrix and curves are this close to sRGB is left alone, sRGB profiles of
+// different vendors differ in the last digits
+const SRGB_TOLERANCE: f32 = 2e-3;
+
+// a tone reproduction curve, encoded value in [0, 1] to linear light
+#[derive(Clone, Debug)]
+pub enum ToneCurve {
+    Gamma(f32),
+    // equally spaced samples, linearly interpolated
+    Table(Vec<f32>),
+    // function type 0 to 4 of the parametricCurveType, parameters g, a, b, c, d, e, f
+    Parametric { kind: u16, parameters: [f32; 7] },
+}
+
+impl ToneCurve {
+    pub fn linearize(&self, v: f32) -> f32 {
+        let v = v.clamp(0.0, 1.0);
+        match self {
+            ToneCurve::Gamma(gamma) => v.powf(*gamma),
+            ToneCurve::Table(table) => {
+                let position = v * (table.len() - 1) as f32;
+                let index = (position.floor() as usize).min(table.len() - 2);
+                let t = position - index as f32;
+                table[index] * (1.0 - t) + table[index + 1] * t
+            }
+            ToneCurve::Parametric { kind, parameters } => {
+                let [g, a, b, c, d, e, f] = *parameters;
+                let power = |v: f32| (a * v + b).max(0.0).powf(g);
+                match kind {
+                    0 => v.powf(g),
+                    1 => if v >= -b / a { power(v) } else { 0.0 },
+                    2 => if v >= -b / a { power(v) + c } else { c },
+                    3 => if v >= d { power(v) } else { c * v },
+                    _ => if v >= d { power(v) + e } else { c * v + f },
+                }
+            }
+        }
+    }
+}
+
+// an RGB matrix/TRC profile
+#[derive(Clone, Debug)]
+pub struct MatrixProfile {
+    // linear rgb to D50 XYZ, the rXYZ, gXYZ and bXYZ tags as columns
+    pub to_xyz: [[f32; 3]; 3],
+    pub curves: [ToneCurve; 3],
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+// s15Fixed16Number, section 4.6
+fn read_fixed(bytes: &[u8], offset: usize) -> Option<f32> {
+    Some(read_u32(bytes, offset)? as i32 as f32 / 65536.0)
+}
+
+// the data of the tag with the given signature, from the tag table that follows the 128 byte
+// header (section 7.3)
+fn find_tag<'a>(profile: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+    let count = read_u32(profile, 128)? as usize;
+    (0..count).find_map(|i| {
+        let entry = 132 + i * 12;
+        if profile.get(entry..entry + 4)? != signature {
+            return None;
+        }
+        let offset = read_u32(profile, entry + 4)? as usize;
+        let size = read_u32(profile, entry + 8)? as usize;
+        profile.get(offset..offset.checked_add(size)?)
+    })
+}
+
+// XYZType, section 10.31
+fn read_xyz(tag: &[u8]) -> Option<[f32; 3]> {
+    if tag.get(0..4)? != b"XYZ " {
+        return None;
+    }
+    Some([read_fixed(tag, 8)?, read_fixed(tag, 12)?, read_fixed(tag, 16)?])
+}
+
+// curveType (section 10.6) or parametricCurveType (section 10.18)
+fn read_curve(tag: &[u8]) -> Option<ToneCurve> {
+    match tag.get(0..4)? {
+        b"curv" => {
+            let count = read_u32(tag, 8)? as usize;
+            match count {
+                0 => Some(ToneCurve::Gamma(1.0)),
+                // u8Fixed8Number
+                1 => Some(ToneCurve::Gamma(read_u16(tag, 12)? as f32 / 256.0)),
+                _ => (0..count)
+                    .map(|i| read_u16(tag, 12 + i * 2).map(|v| v as f32 / 65535.0))
+                    .collect::<Option<Vec<f32>>>()
+                    .map(ToneCurve::Table),
+            }
+        }
+        b"para" => {
+            let kind = read_u16(tag, 8)?;
+            let count = [1, 3, 4, 5, 7].get(kind as usize)?;
+            let mut parameters = [0.0; 7];
+            for (i, parameter) in parameters.iter_mut().take(*count).enumerate() {
+                *parameter = read_fixed(tag, 12 + i * 4)?;
+            }
+            Some(ToneCurve::Parametric { kind, parameters })
+        }
+        _ => None,
+    }
+}
+
+// the profileDescriptionTag, textDescriptionType in version 2 profiles and
+// multiLocalizedUnicodeType (first record) in version 4
+pub fn profile_description(profile: &[u8]) -> Option<String> {
+    let tag = find_tag(profile, b"desc")?;
+    let text = match tag.get(0..4)? {
+        b"desc" => {
+            let length = read_u32(tag, 8)? as usize;
+            String::from_utf8_lossy(tag.get(12..12 + length)?).to_string()
+        }
+        b"mluc" => {
+            let length = read_u32(tag, 20)? as usize;
+            let offset = read_u32(tag, 24)? as usize;
+            let units: Vec<u16> = tag.get(offset..offset + length)?
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => return None,
+    };
+    Some(text.trim_end_matches('\0').trim().to_string())
+}
+
+impl MatrixProfile {
+    // None for profiles that are not RGB to XYZ matrix/TRC profiles
+    pub fn parse(profile: &[u8]) -> Option<MatrixProfile> {
+        if profile.get(16..20)? != b"RGB " || profile.get(20..24)? != b"XYZ " {
+            return None;
+        }
+        let r = read_xyz(find_tag(profile, b"rXYZ")?)?;
+        let g = read_xyz(find_tag(profile, b"gXYZ")?)?;
+        let b = read_xyz(find_tag(profile, b"bXYZ")?)?;
+        Some(MatrixProfile {
+            to_xyz: [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]],
+            curves: [
+                read_curve(find_tag(profile, b"rTRC")?)?,
+                read_curve(find_tag(profile, b"gTRC")?)?,
+                read_curve(find_tag(profile, b"bTRC")?)?,
+            ],
+        })
+    }
+
+    // linear profile rgb to linear sRGB
+    pub fn to_linear_srgb(&self) -> [[f32; 3]; 3] {
+        multiply(&invert(&SRGB_D50), &self.to_xyz)
+    }
+
+    // whether converting would not change anything beyond rounding
+    pub fn is_srgb(&self) -> bool {
+        let matrix = self.to_linear_srgb();
+        let identity = (0..3).all(|i| (0..3).all(|j| {
+            (matrix[i][j] - if i == j { 1.0 } else { 0.0 }).abs() < SRGB_TOLERANCE
+        }));
+        identity && self.curves.iter().all(|curve| {
+            (0..=16).all(|i| {
+                let v = i as f32 / 16.0;
+                (linear_to_srgb(curve.linearize(v)) - v).abs() < SRGB_TOLERANCE
+            })
+        })
+    }
+
+    // converts an 8 or 16 bit rgb(a) image to sRGB in place, keeping its bit depth and alpha.
+    // Returns false for images it does not apply to (gray, float)
+    pub fn convert_to_srgb(&self, image: &mut DynamicImage) -> bool {
+        let matrix = self.to_linear_srgb();
+        match image {
+            DynamicImage::ImageRgb8(image) => self.convert_samples(image, 3, &matrix, to_8bit),
+            DynamicImage::ImageRgba8(image) => self.convert_samples(image, 4, &matrix, to_8bit),
+            DynamicImage::ImageRgb16(image) => self.convert_samples(image, 3, &matrix, to_16bit),
+            DynamicImage::ImageRgba16(image) => self.convert_samples(image, 4, &matrix, to_16bit),
+            _ => return false,
+        }
+        true
+    }
+
+    // the curves are evaluated once per possible sample value
+    fn convert_samples<T>(&self, samples: &mut [T], channels: usize, matrix: &[[f32; 3]; 3], encode: fn(f32) -> T)
+    where
+        T: Copy + Into<u32> + Send + Sync,
+    {
+        let levels = if std::mem::size_of::<T>() == 1 { 256 } else { 65536 };
+        let tables: Vec<Vec<f32>> = self.curves.iter()
+            .map(|curve| (0..levels).map(|i| curve.linearize(i as f32 / (levels - 1) as f32)).collect())
+            .collect();
+        samples.par_chunks_exact_mut(channels).for_each(|pixel| {
+            let linear = [0, 1, 2].map(|c| tables[c][pixel[c].into() as usize]);
+            for (c, row) in matrix.iter().enumerate() {
+                let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                pixel[c] = encode(linear_to_srgb(value.clamp(0.0, 1.0)));
+            }
+        });
+    }
+}
+
+fn to_16bit(v: f32) -> u16 {
+    (v.clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+fn multiply(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let mut output = [[0.0; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            output[i][j] = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    output
+}
+
+// by cofactors, the matrices here are well conditioned
+fn invert(m: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant: f32 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+    let mut output = [[0.0; 3]; 3];
+    for (i, row) in output.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            // the inverse is the transposed cofactor matrix over the determinant
+            *value = cofactor(j, i) / determinant;
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Adobe RGB (1998) relative to D50, a wide gamut working space
+    pub(crate) const ADOBE_RGB_D50: [[f32; 3]; 3] = [
+        [0.6097559, 0.2052401, 0.149224],
+        [0.3111242, 0.625656, 0.0632197],
+        [0.0194811, 0.0608902, 0.7448387],
+    ];
+
+    // the sRGB curve as parametric type 3
+    pub(crate) fn srgb_curve() -> ToneCurve {
+        ToneCurve::Parametric { kind: 3, parameters: [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045, 0.0, 0.0] }
+    }
+
+    pub(crate) fn srgb_profile(description: &str) -> Vec<u8> {
+        profile_bytes(&SRGB_D50, &srgb_curve(), description)
+    }
+
+    fn fixed(v: f32) -> [u8; 4] {
+        ((v * 65536.0).round() as i32).to_be_bytes()
+    }
+
+    // a version 2 matrix/TRC profile with the same parametric curve on all channels
+    pub(crate) fn profile_bytes(to_xyz: &[[f32; 3]; 3], curve: &ToneCurve, description: &str) -> Vec<u8> {
+        let ToneCurve::Parametric { kind, parameters } = curve else { panic!("parametric curves only") };
+        let mut tags: Vec<(&[u8; 4], Vec<u8>)> = Vec::new();
+        for (signature, column) in [(b"rXYZ", 0), (b"gXYZ", 1), (b"bXYZ", 2)] {
+            let mut data = b"XYZ \0\0\0\0".to_vec();
+            (0..3).for_each(|row| data.extend(fixed(to_xyz[row][column])));
+            tags.push((signature, data));
+        }
+        let mut para = b"para\0\0\0\0".to_vec();
+        para.extend(kind.to_be_bytes());
+        para.extend([0, 0]);
+        parameters.iter().take([1, 3, 4, 5, 7][*kind as usize]).for_each(|v| para.extend(fixed(*v)));
+        for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+            tags.push((signature, para.clone()));
+        }
+        let mut desc = b"desc\0\0\0\0".to_vec();
+        desc.extend((description.len() as u32 + 1).to_be_bytes());
+        desc.extend(description.as_bytes());
+        desc.push(0);
+        tags.push((b"desc", desc));
+
+        let mut profile = vec![0u8; 128];
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile[20..24].copy_from_slice(b"XYZ ");
+        profile.extend((tags.len() as u32).to_be_bytes());
+        let mut offset = 132 + tags.len() * 12;
+        for (signature, data) in &tags {
+            profile.extend(*signature);
+            profile.extend((offset as u32).to_be_bytes());
+            profile.extend((data.len() as u32).to_be_bytes());
+            offset += data.len();
+        }
+        tags.iter().for_each(|(_, data)| profile.extend(data));
+        let size = (profile.len() as u32).to_be_bytes();
+        profile[0..4].copy_from_slice(&size);
+        profile
+    }
+
+    #[test]
+    fn curves_linearize() {
+        assert_eq!(ToneCurve::Gamma(2.0).linearize(0.5), 0.25);
+        assert_eq!(ToneCurve::Gamma(2.0).linearize(1.5), 1.0);
+
+        let table = ToneCurve::Table(vec![0.0, 0.2, 1.0]);
+        assert!((table.linearize(0.25) - 0.1).abs() < 1e-6);
+        assert!((table.linearize(0.75) - 0.6).abs() < 1e-6);
+        assert_eq!(table.linearize(1.0), 1.0);
+
+        let power = ToneCurve::Parametric { kind: 0, parameters: [2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0] };
+        assert_eq!(power.linearize(0.5), 0.25);
+        // type 1 is 0 below -b / a
+        let offset = ToneCurve::Parametric { kind: 1, parameters: [1.0, 1.0, -0.5, 0.0, 0.0, 0.0, 0.0] };
+        assert_eq!(offset.linearize(0.25), 0.0);
+        assert_eq!(offset.linearize(0.75), 0.25);
+        // type 3 has a linear toe below d
+        assert!((srgb_curve().linearize(0.02) - 0.02 / 12.92).abs() < 1e-7);
+        assert!((0..=10).all(|i| (linear_to_srgb(srgb_curve().linearize(i as f32 / 10.0)) - i as f32 / 10.0).abs() < 1e-5));
+    }
+
+    #[test]
+    fn srgb_profiles_are_detected() {
+        let srgb = MatrixProfile::parse(&srgb_profile("sRGB")).unwrap();
+        assert!(srgb.is_srgb());
+        assert_eq!(profile_description(&srgb_profile("sRGB")).as_deref(), Some("sRGB"));
+
+        let gamma = ToneCurve::Parametric { kind: 0, parameters: [2.2, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0] };
+        assert!(!MatrixProfile::parse(&profile_bytes(&SRGB_D50, &gamma, "gamma 2.2")).unwrap().is_srgb());
+        assert!(!MatrixProfile::parse(&profile_bytes(&ADOBE_RGB_D50, &srgb_curve(), "wide")).unwrap().is_srgb());
+    }
+
+    #[test]
+    fn wide_gamut_colors_are_converted() {
+        let profile = MatrixProfile::parse(&profile_bytes(&ADOBE_RGB_D50, &srgb_curve(), "wide")).unwrap();
+        // Adobe RGB green lies outside sRGB and is clipped, gray stays gray
+        let mut image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(2, 1, |x, _| {
+            if x == 0 { image::Rgb([0, 255, 0]) } else { image::Rgb([128, 128, 128]) }
+        }));
+        assert!(profile.convert_to_srgb(&mut image));
+        let image = image.into_rgb8();
+        assert_eq!(image.get_pixel(0, 0)[1], 255);
+        assert!(image.get_pixel(0, 0)[0] == 0 && image.get_pixel(0, 0)[2] == 0);
+        assert!(image.get_pixel(1, 0).0.iter().all(|v| v.abs_diff(128) <= 1));
+
+        let mut float = DynamicImage::ImageRgb32F(image::Rgb32FImage::new(1, 1));
+        assert!(!profile.convert_to_srgb(&mut float));
+    }
+}
@@ -1,21 +1,14 @@
 use std::{f32::consts::{E, PI}, iter::Map, collections::HashMap};
-use image::{GrayImage, Pixel, Rgb, Luma};
+use image::{GrayImage, Pixel, Rgb};
 use num::integer::Roots;
+use crate::colorfulness::Luminance;
+use crate::mask::{included, window_included, Mask};
+use crate::plane::{gray_to_plane, Plane};
+use crate::simd::convolve_3x3;
+use rayon::prelude::*;
 use crate::utils::{_2d_array_to_vec, GAUSS_SMOOTH, matrix_multiply, SOBEL_X, SOBEL_Y, DIR_MAT_Y, DIR_MAT_X};
 
-pub fn rgb_image_to_2d_vec(pixels: &image::RgbImage) -> Vec<Vec<Rgb<u8>>> {
-    let mut out: Vec<Vec<Rgb<u8>>> = Vec::new();
-    for x in 0..pixels.width() {
-        let mut row: Vec<Rgb<u8>> = Vec::new();
-        for y in 0..pixels.height() {
-            row.push(*pixels.get_pixel(x, y));
-        }
-        out.push(row);
-    }
-    out
-}
-
-pub fn sobel_convolution(pixels: &image::Rgb32FImage) -> image::Rgb32FImage {
+pub fn sobel_convolution(pixels: &image::Rgb32FImage, luminance: Luminance) -> image::Rgb32FImage {
     let mut output = image::Rgb32FImage::new(pixels.width(), pixels.height());
     let (width, height) = pixels.dimensions();
 
@@ -27,8 +20,9 @@ pub fn sobel_convolution(pixels: &image::Rgb32FImage) -> image::Rgb32FImage {
             for i in 0..3 {
                 for j in 0..3 {
                     let pix = pixels.get_pixel(x + i, y + j);
-                    sum_x += ((pix[0] + pix[1] + pix[2]) / 3.0) * SOBEL_X[i as usize][j as usize];
-                    sum_y += ((pix[0] + pix[1] + pix[2]) / 3.0) * SOBEL_Y[i as usize][j as usize];
+                    let gray = luminance.gray(pix[0], pix[1], pix[2]);
+                    sum_x += gray * SOBEL_X[i as usize][j as usize];
+                    sum_y += gray * SOBEL_Y[i as usize][j as usize];
                 }
             }
             sum_x = sum_x.abs();
@@ -46,14 +40,14 @@ pub fn sobel_convolution(pixels: &image::Rgb32FImage) -> image::Rgb32FImage {
     output
 }
 
-pub fn apply_threshold(pixels: &image::Rgb32FImage, threshold: f32) -> image::Rgb32FImage {
+pub fn apply_threshold(pixels: &image::Rgb32FImage, threshold: f32, luminance: Luminance) -> image::Rgb32FImage {
     let mut output = image::Rgb32FImage::new(pixels.width(), pixels.height());
     let (width, height) = pixels.dimensions();
 
     for x in 0..width {
         for y in 0..height {
             let pix = pixels.get_pixel(x, y);
-            let sum = (pix[0] + pix[1] + pix[2]) / 3.0;
+            let sum = luminance.gray(pix[0], pix[1], pix[2]);
             if sum > threshold {
                 output.put_pixel(x, y, Rgb::<f32>([1.0, 1.0, 1.0]));
             } else {
@@ -65,7 +59,7 @@ pub fn apply_threshold(pixels: &image::Rgb32FImage, threshold: f32) -> image::Rg
     output
 }
 
-pub fn apply_fuzzy_threshold(pixels: &image::Rgb32FImage, t_low: f32, t_high: f32) -> Vec<image::Rgb32FImage> {
+pub fn apply_fuzzy_threshold(pixels: &image::Rgb32FImage, t_low: f32, t_high: f32, luminance: Luminance) -> Vec<image::Rgb32FImage> {
     let mut level1 = image::Rgb32FImage::new(pixels.width(), pixels.height());
     let mut level2 = image::Rgb32FImage::new(pixels.width(), pixels.height());
     let mut level3 = image::Rgb32FImage::new(pixels.width(), pixels.height());
@@ -74,7 +68,7 @@ pub fn apply_fuzzy_threshold(pixels: &image::Rgb32FImage, t_low: f32, t_high: f3
     for x in 0..width {
         for y in 0..height {
             let pix = pixels.get_pixel(x, y);
-            let sum = (pix[0] + pix[1] + pix[2]) / 3.0;
+            let sum = luminance.gray(pix[0], pix[1], pix[2]);
             if sum > t_high {
                 level1.put_pixel(x, y, Rgb::<f32>([1.0, 1.0, 1.0]));
                 level2.put_pixel(x, y, Rgb::<f32>([0.0, 0.0, 0.0]));
@@ -95,12 +89,18 @@ pub fn apply_fuzzy_threshold(pixels: &image::Rgb32FImage, t_low: f32, t_high: f3
     vec![level1, level2, level3]
 }
 
-pub fn edge_pixels_ratio(pixels: GrayImage) -> f32 {
+// with a mask the ratio is taken over the included pixels only
+pub fn edge_pixels_ratio(pixels: &GrayImage, mask: Option<&Mask>) -> f32 {
     let mut white = 0;
+    let mut area = 0;
     let (width, height) = pixels.dimensions();
 
     for x in 0..width {
         for y in 0..height {
+            if !included(mask, x, y) {
+                continue;
+            }
+            area += 1;
             let pix = pixels.get_pixel(x, y);
             if pix[0] > 245 {
                 white += 1;
@@ -108,22 +108,22 @@ pub fn edge_pixels_ratio(pixels: GrayImage) -> f32 {
         }
     }
 
-    white as f32 / (pixels.width() * pixels.height()) as f32
+    white as f32 / area.max(1) as f32
 }
 
 // see https://ieeexplore.ieee.org/document/4309999
 // page 6. Equation 1.
 // 'size' is the size of the neighborhood calculated as 2^k
-pub fn neighborhood_average(pixels: &Vec<Vec<u8>>, x: i32, y: i32, size: u32) -> f32 {
+pub fn neighborhood_average(pixels: &Plane<u8>, x: i32, y: i32, size: u32) -> f32 {
     let mut out = 0.0f32;
-    let (width, height) = (pixels.len(), pixels[0].len());
+    let (width, height) = (pixels.width(), pixels.height());
     for i in (x - 2i32.pow(size - 1))..(x + 2i32.pow(size - 1) - 1) {
         for j in (y - 2i32.pow(size - 1))..(y + 2i32.pow(size - 1) - 1) {
             // not a word has been said about going out of bounds in the paper but let's assume that it counts as 0
             if i < 0 || j < 0  || i >= width as i32 || j >= height as i32 {
                 continue;
             }
-            out += pixels[i as usize][j as usize] as f32;
+            out += pixels[(i as usize, j as usize)] as f32;
         }
     }
     out / 2u32.pow(2 * size) as f32
@@ -132,7 +132,7 @@ pub fn neighborhood_average(pixels: &Vec<Vec<u8>>, x: i32, y: i32, size: u32) ->
 // see https://ieeexplore.ieee.org/document/4309999
 // page 6. Equation 2, 3 and 4.
 // 'size' is the size of the neighborhood calculated as 2^k
-pub fn s_best(pixels: &Vec<Vec<u8>>, x: i32, y: i32) -> u32 {
+pub fn s_best(pixels: &Plane<u8>, x: i32, y: i32) -> u32 {
     let mut e_vec: Vec<(f32, u8)> = Vec::new();
     for size in 1..5 {
         let e_1 = neighborhood_average(pixels, x + 2i32.pow(size - 1), y, size);
@@ -151,33 +151,37 @@ pub fn s_best(pixels: &Vec<Vec<u8>>, x: i32, y: i32) -> u32 {
     best.1 as u32
 }
 
-pub fn coarseness(pixels: &GrayImage) -> f32 {
-    let mut out = 0.0f32;
-    let (width, height) = pixels.dimensions();
-
-    // todo get a vec of pixels to avoid using get_pixels
-    let mut pixels_vec: Vec<Vec<u8>> = Vec::new();
-
-    for i in 0..width {
-        let mut row: Vec<u8> = Vec::new();
-        for j in 0..height {
-            row.push(pixels[(i, j)][0]);
-        }
-        pixels_vec.push(row);
-    }
-
-    for i in 0..width {
-        for j in 0..height {
-            let s_best = s_best(&pixels_vec, i as i32, j as i32);
-            out += s_best as f32;
-        }
-    }
+// with a mask the best sizes are averaged over the included pixels, the neighborhoods
+// themselves still see the whole image
+pub fn coarseness(pixels: &Plane<u8>, mask: Option<&Mask>) -> f32 {
+    let (width, height) = (pixels.width() as u32, pixels.height() as u32);
+
+    // columns run in parallel, the sums are integers so the result does not depend on the
+    // thread count
+    let (out, area) = (0..width)
+        .into_par_iter()
+        .map(|i| {
+            let mut column = (0u64, 0u64);
+            for j in 0..height {
+                if !included(mask, i, j) {
+                    continue;
+                }
+                column.0 += s_best(pixels, i as i32, j as i32) as u64;
+                column.1 += 1;
+            }
+            column
+        })
+        .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
 
-    out / (width * height) as f32
+    out as f32 / area.max(1) as f32
 }
 
 // DIRECTIONALITY
 
+fn transposed(kernel: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    [0, 1, 2].map(|i| [kernel[0][i], kernel[1][i], kernel[2][i]])
+}
+
 // page 8 first equation on the right
 pub fn quantized_peaks(vec: &Vec<f32>, n: i32) -> Vec<f32> {
     let mut divisor: f32 = 0.0;
@@ -203,11 +207,6 @@ pub fn quantized_peaks(vec: &Vec<f32>, n: i32) -> Vec<f32> {
         histogram[k as usize] = count as f32 / divisor;
     }
 
-    // print
-    for i in 0..histogram.len() {
-        println!("{}: {}", i, histogram[i]);
-    }
-
     histogram
 }
 
@@ -309,62 +308,47 @@ fn find_peaks(vec: &Vec<f32>) -> Vec<Peak> {
         } 
     }
 
-    //print
-    for i in 0..out.len() {
-        println!("peak {}: value: {}, idx: {}, range: {:?}", i, out[i].value, out[i].idx, out[i].range);
-    }
-
     out
 }
 
-pub fn directionality(pixels: &GrayImage, threshold: f32, n: i32) -> f32 {
+// with a mask only edges centered on included pixels go into the histogram
+pub fn directionality(pixels: &Plane<u8>, threshold: f32, n: i32, mask: Option<&Mask>) -> f32 {
     // calculate direction of edge at each pixel
     let mut out = 0.0f32;
-    let (width, height) = pixels.dimensions();
-
-    // todo get a vec of pixels to avoid using get_pixels
-    let mut pixels_vec: Vec<Vec<u8>> = Vec::new();
-    let mut angles: Vec<f32> = Vec::new();
-    let mut image_out = GrayImage::new(width, height);
-
-    for i in 0..width {
-        let mut row: Vec<u8> = Vec::new();
-        for j in 0..height {
-            row.push(pixels[(i, j)][0]);
-        }
-        pixels_vec.push(row);
-    }
-
-    // convolution
-    for x in 0..(width - 2) {
-        for y in 0..(height - 2) {
-            let mut sum_x = 0.0;
-            let mut sum_y = 0.0;
-
-            for i in 0..3 {
-                for j in 0..3 {
-                    sum_x += pixels_vec[(x + i) as usize][(y + j) as usize] as f32 * DIR_MAT_X[i as usize][j as usize];
-                    sum_y += pixels_vec[(x + i) as usize][(y + j) as usize] as f32 * DIR_MAT_Y[i as usize][j as usize];
+    // convolution, the matrices are indexed [dx][dy] so they are transposed for convolve_3x3.
+    // gradients[(x, y)] belongs to the 3x3 window starting at (x, y)
+    let plane = pixels.map(|v| v as f32);
+    let gradients_x = convolve_3x3(&plane, &transposed(&DIR_MAT_X));
+    let gradients_y = convolve_3x3(&plane, &transposed(&DIR_MAT_Y));
+    let (width, height) = (gradients_x.width() as u32, gradients_x.height() as u32);
+
+    // columns run in parallel and are concatenated in order
+    let angles: Vec<f32> = (0..width)
+        .into_par_iter()
+        .flat_map_iter(|x| {
+            let mut column = Vec::new();
+            for y in 0..height {
+                let sum_x = gradients_x[(x as usize, y as usize)].abs();
+                let sum_y = gradients_y[(x as usize, y as usize)].abs();
+
+                // thats what i assume was meant by thresholding so that we dont count insignificant data
+                if sum_x < threshold && sum_y < threshold {
+                    continue;
                 }
-            }
 
-            sum_x = sum_x.abs();
-            sum_y = sum_y.abs();
+                // the gradient at (x + 1, y + 1) needs its whole 3x3 window inside the mask
+                if !window_included(mask, x, y, 3) {
+                    continue;
+                }
 
-            let pixel_val = (sum_x.powf(2.0) + sum_y.powf(2.0)).sqrt();
-            image_out.put_pixel(x, y, Luma([pixel_val as u8]));
+                let arg = sum_y / sum_x;
 
-            // thats what i assume was meant by thresholding so that we dont count insignificant data
-            if sum_x < threshold && sum_y < threshold {
-                continue;
+                let angle = arg.atan() + PI / 2.0;
+                column.push(angle);
             }
-
-            let arg = sum_y / sum_x;
-
-            let angle = arg.atan() + PI / 2.0;
-            angles.push(angle);
-        }
-    }
+            column
+        })
+        .collect();
 
     let hd = quantized_peaks(&angles, n);
     let peaks = find_peaks(&hd);
@@ -386,4 +370,460 @@ pub fn directionality(pixels: &GrayImage, threshold: f32, n: i32) -> f32 {
     out = peaks.len() as f32 * temp;
 
     out
-}
\ No newline at end of file
+}
+
+// SYMMETRY
+
+// symmetry is searched on a downscaled copy, the axis search is quadratic in the width
+const SYMMETRY_MAX_SIDE: u32 = 256;
+// the mirror axis is searched in the central part of the image, so at least half of it overlaps
+const MIRROR_AXIS_RANGE: (f32, f32) = (0.25, 0.75);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MirrorAxis {
+    // vertical axis, left half mirrored onto the right half
+    Vertical,
+    // horizontal axis, top half mirrored onto the bottom half
+    Horizontal,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SymmetryMode {
+    // pearson correlation of the intensities of mirrored pixels
+    Pixel,
+    // normalized dot product of the gradients of mirrored pixels, with the gradient transformed
+    // by the same mirror / rotation. Ignores flat areas and global brightness changes
+    Gradient,
+}
+
+pub struct MirrorSymmetry {
+    // correlation in [-1, 1], 1 for a perfect mirror image
+    pub score: f32,
+    // position of the best axis in [0, 1] of the width (vertical) or height (horizontal)
+    pub axis: f32,
+}
+
+pub struct RotationalSymmetry {
+    // 180 degree rotation about the image center
+    pub order_2: f32,
+    // 90 degree rotation about the center of the largest centered square
+    pub order_4: f32,
+}
+
+// a pixel and its mirrored / rotated counterpart, as (x, y) pairs
+type PixelPair = ((usize, usize), (usize, usize));
+
+struct SymmetryPlane {
+    values: Plane<f32>,
+    gradients: Plane<(f32, f32)>,
+}
+
+impl SymmetryPlane {
+    fn new(values: Plane<f32>) -> SymmetryPlane {
+        let (width, height) = (values.width(), values.height());
+        let gradients = Plane::from_fn(width, height, |x, y| {
+            let gx = values[((x + 1).min(width - 1), y)] - values[(x.saturating_sub(1), y)];
+            let gy = values[(x, (y + 1).min(height - 1))] - values[(x, y.saturating_sub(1))];
+            (gx / 2.0, gy / 2.0)
+        });
+        SymmetryPlane { values, gradients }
+    }
+
+    fn width(&self) -> usize {
+        self.values.width()
+    }
+
+    fn height(&self) -> usize {
+        self.values.height()
+    }
+
+    // correlation between every pixel from `pairs` and its counterpart. `transform` maps the
+    // gradient at the counterpart back into the frame of the first pixel
+    fn correlation<T>(&self, pairs: &[PixelPair], mode: SymmetryMode, transform: T) -> f32
+        where T: Fn((f32, f32)) -> (f32, f32) {
+        if pairs.is_empty() {
+            return 0.0;
+        }
+        match mode {
+            SymmetryMode::Pixel => {
+                let n = pairs.len() as f64;
+                let (mut s1, mut s2, mut s11, mut s22, mut s12) = (0.0f64, 0.0f64, 0.0f64, 0.0f64, 0.0f64);
+                for ((x1, y1), (x2, y2)) in pairs {
+                    let a = self.values[(*x1, *y1)] as f64;
+                    let b = self.values[(*x2, *y2)] as f64;
+                    s1 += a;
+                    s2 += b;
+                    s11 += a * a;
+                    s22 += b * b;
+                    s12 += a * b;
+                }
+                let covariance = s12 / n - (s1 / n) * (s2 / n);
+                let variance = (s11 / n - (s1 / n).powi(2)) * (s22 / n - (s2 / n).powi(2));
+                // flat image, mirrored or not it looks the same
+                if variance <= 1e-12 {
+                    return 1.0;
+                }
+                (covariance / variance.sqrt()) as f32
+            }
+            SymmetryMode::Gradient => {
+                let (mut dot, mut n1, mut n2) = (0.0f64, 0.0f64, 0.0f64);
+                for ((x1, y1), (x2, y2)) in pairs {
+                    let g1 = self.gradients[(*x1, *y1)];
+                    let g2 = transform(self.gradients[(*x2, *y2)]);
+                    dot += (g1.0 * g2.0 + g1.1 * g2.1) as f64;
+                    n1 += (g1.0.powi(2) + g1.1.powi(2)) as f64;
+                    n2 += (g2.0.powi(2) + g2.1.powi(2)) as f64;
+                }
+                if n1 <= 1e-12 || n2 <= 1e-12 {
+                    return 1.0;
+                }
+                (dot / (n1 * n2).sqrt()) as f32
+            }
+        }
+    }
+}
+
+fn symmetry_plane(pixels: &GrayImage, transpose: bool) -> SymmetryPlane {
+    let (width, height) = pixels.dimensions();
+    let scale = (SYMMETRY_MAX_SIDE as f32 / width.max(height) as f32).min(1.0);
+    let small = image::imageops::resize(
+        pixels,
+        ((width as f32 * scale) as u32).max(1),
+        ((height as f32 * scale) as u32).max(1),
+        image::imageops::FilterType::Triangle,
+    );
+
+    let values = gray_to_plane(&small);
+    SymmetryPlane::new(if transpose { values.transpose() } else { values })
+}
+
+// mirror symmetry about the best axis found by searching the central half of the image.
+// the horizontal axis is handled by transposing the image and searching a vertical one
+pub fn mirror_symmetry(pixels: &GrayImage, axis: MirrorAxis, mode: SymmetryMode) -> MirrorSymmetry {
+    let plane = symmetry_plane(pixels, axis == MirrorAxis::Horizontal);
+    let width = plane.width();
+    let height = plane.height();
+
+    let mut best = MirrorSymmetry { score: -1.0, axis: 0.5 };
+    // the axis runs through pixel centers (even) or between two pixels (odd), in half pixel units
+    let first = ((2 * width) as f32 * MIRROR_AXIS_RANGE.0) as usize;
+    let last = ((2 * width) as f32 * MIRROR_AXIS_RANGE.1) as usize;
+    for doubled_axis in first..=last.max(first) {
+        let mut pairs = Vec::new();
+        for y in 0..height {
+            // pixel x1 mirrors onto x2 = doubled_axis - x1
+            for x1 in 0..width {
+                if 2 * x1 >= doubled_axis {
+                    break;
+                }
+                let x2 = doubled_axis - x1;
+                if x2 >= width {
+                    continue;
+                }
+                pairs.push(((x1, y), (x2, y)));
+            }
+        }
+        let score = plane.correlation(&pairs, mode, |(gx, gy)| (-gx, gy));
+        if score > best.score {
+            best = MirrorSymmetry { score, axis: (doubled_axis as f32 / 2.0 + 0.5) / width as f32 };
+        }
+    }
+
+    best
+}
+
+pub fn rotational_symmetry(pixels: &GrayImage, mode: SymmetryMode) -> RotationalSymmetry {
+    let plane = symmetry_plane(pixels, false);
+    let width = plane.width();
+    let height = plane.height();
+
+    // 180 degrees, (x, y) -> (w - 1 - x, h - 1 - y), the gradient flips
+    let mut pairs = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            pairs.push(((x, y), (width - 1 - x, height - 1 - y)));
+        }
+    }
+    let order_2 = plane.correlation(&pairs, mode, |(gx, gy)| (-gx, -gy));
+
+    // 90 degrees inside the centered square, (dx, dy) -> (-dy, dx) around its center
+    let side = width.min(height);
+    let (ox, oy) = ((width - side) / 2, (height - side) / 2);
+    let mut pairs = Vec::new();
+    for y in 0..side {
+        for x in 0..side {
+            pairs.push(((ox + x, oy + y), (ox + side - 1 - y, oy + x)));
+        }
+    }
+    let order_4 = plane.correlation(&pairs, mode, |(gx, gy)| (gy, -gx));
+
+    RotationalSymmetry { order_2, order_4 }
+}
+
+// ENTROPY AND SPATIAL INFORMATION
+
+pub fn entropy_of_histogram(histogram: &[u64]) -> f32 {
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let mut out = 0.0f64;
+    for count in histogram.iter().filter(|c| **c > 0) {
+        let p = *count as f64 / total as f64;
+        out -= p * p.log2();
+    }
+    out as f32
+}
+
+// shannon entropy of the gray level histogram, in bits (0 - 8). With a mask the histogram only
+// holds the included pixels
+pub fn gray_entropy(pixels: &GrayImage, mask: Option<&Mask>) -> f32 {
+    let mut histogram = [0u64; 256];
+    for (x, y, pix) in pixels.enumerate_pixels() {
+        if included(mask, x, y) {
+            histogram[pix[0] as usize] += 1;
+        }
+    }
+    entropy_of_histogram(&histogram)
+}
+
+// entropy of every channel separately, in r, g, b order
+pub fn channel_entropy(image: &image::RgbImage) -> [f32; 3] {
+    let mut histograms = [[0u64; 256]; 3];
+    for pix in image.pixels() {
+        for (histogram, value) in histograms.iter_mut().zip(pix.0.iter()) {
+            histogram[*value as usize] += 1;
+        }
+    }
+    histograms.map(|histogram| entropy_of_histogram(&histogram))
+}
+
+// two dimensional entropy of (gray level, mean gray level of the 8 neighbours) pairs, in bits (0 - 16)
+// see A. S. Abutaleb, "Automatic thresholding of gray-level pictures using two-dimensional entropy", 1989
+pub fn entropy_2d(pixels: &GrayImage) -> f32 {
+    let (width, height) = pixels.dimensions();
+    let mut histogram = vec![0u64; 256 * 256];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0u32;
+            for dy in -1i32..=1 {
+                for dx in -1i32..=1 {
+                    if dx == 0 && dy == 0 {
+                        continue;
+                    }
+                    let nx = (x as i32 + dx).clamp(0, width as i32 - 1) as u32;
+                    let ny = (y as i32 + dy).clamp(0, height as i32 - 1) as u32;
+                    sum += pixels.get_pixel(nx, ny)[0] as u32;
+                }
+            }
+            let mean = ((sum as f32) / 8.0).round() as usize;
+            histogram[pixels.get_pixel(x, y)[0] as usize * 256 + mean] += 1;
+        }
+    }
+    entropy_of_histogram(&histogram)
+}
+
+// entropy of the gray levels inside a window x window neighbourhood of every pixel (window should
+// be odd), borders are replicated.
+// the histogram slides along each row and the entropy is kept up to date incrementally using
+// H = log2(N) - sum(c * log2(c)) / N
+pub fn local_entropy_map(pixels: &GrayImage, window: u32) -> Plane<f32> {
+    let (width, height) = pixels.dimensions();
+    let radius = (window / 2) as i32;
+    let n = ((2 * radius + 1) * (2 * radius + 1)) as usize;
+    let c_log_c: Vec<f64> = (0..=n).map(|c| if c == 0 { 0.0 } else { c as f64 * (c as f64).log2() }).collect();
+    let log_n = (n as f64).log2();
+
+    let value = |x: i32, y: i32| {
+        let cx = x.clamp(0, width as i32 - 1) as u32;
+        let cy = y.clamp(0, height as i32 - 1) as u32;
+        pixels.get_pixel(cx, cy)[0] as usize
+    };
+
+    let add = |histogram: &mut [usize], sum: &mut f64, v: usize, delta: i32| {
+        *sum -= c_log_c[histogram[v]];
+        histogram[v] = (histogram[v] as i32 + delta) as usize;
+        *sum += c_log_c[histogram[v]];
+    };
+
+    let mut out = Plane::new(width as usize, height as usize);
+    for y in 0..height as i32 {
+        let mut histogram = vec![0usize; 256];
+        let mut sum = 0.0f64;
+
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                add(&mut histogram, &mut sum, value(dx, y + dy), 1);
+            }
+        }
+
+        let row = out.row_mut(y as usize);
+        for x in 0..width as i32 {
+            if x > 0 {
+                for dy in -radius..=radius {
+                    add(&mut histogram, &mut sum, value(x - radius - 1, y + dy), -1);
+                    add(&mut histogram, &mut sum, value(x + radius, y + dy), 1);
+                }
+            }
+            row[x as usize] = (log_n - sum / n as f64).max(0.0) as f32;
+        }
+    }
+    out
+}
+
+pub struct SpatialInformation {
+    // the classic ITU-T P.910 SI, standard deviation of the sobel magnitude
+    pub std_dev: f32,
+    // SI_mean and SI_rms from the 2021 revision of P.910
+    pub mean: f32,
+    pub rms: f32,
+}
+
+// see ITU-T Rec. P.910, spatial perceptual information from the sobel gradients of the luma
+// plane (AnalysisContext::gradients()). The one pixel border where the kernels do not fit is left
+// out, see convolve_3x3() for the layout. With a mask only gradients whose 3x3 window lies inside
+// it count
+pub fn spatial_information_from_gradients(gradients_x: &Plane<f32>, gradients_y: &Plane<f32>, mask: Option<&Mask>) -> SpatialInformation {
+    if gradients_x.is_empty() {
+        return SpatialInformation { std_dev: 0.0, mean: 0.0, rms: 0.0 };
+    }
+
+    let mut sum = 0.0f64;
+    let mut sum_sq = 0.0f64;
+    let mut count = 0usize;
+    for (y, (row_x, row_y)) in gradients_x.rows().zip(gradients_y.rows()).enumerate() {
+        for (x, (gx, gy)) in row_x.iter().zip(row_y.iter()).enumerate() {
+            if !window_included(mask, x as u32, y as u32, 3) {
+                continue;
+            }
+            let magnitude = (gx * gx + gy * gy).sqrt() as f64;
+            sum += magnitude;
+            sum_sq += magnitude * magnitude;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return SpatialInformation { std_dev: 0.0, mean: 0.0, rms: 0.0 };
+    }
+
+    let n = count as f64;
+    let mean = sum / n;
+    SpatialInformation {
+        std_dev: (sum_sq / n - mean * mean).max(0.0).sqrt() as f32,
+        mean: mean as f32,
+        rms: (sum_sq / n).sqrt() as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+    use crate::utils::Lcg;
+
+    // deterministic pseudo random gray levels
+    fn noise_image(width: u32, height: u32, seed: u64) -> GrayImage {
+        let mut rng = Lcg(seed);
+        GrayImage::from_fn(width, height, |_, _| Luma([rng.byte()]))
+    }
+
+    #[test]
+    fn mirrored_image_is_symmetric_about_its_center() {
+        let noise = noise_image(32, 48, 1);
+        let mirrored = GrayImage::from_fn(64, 48, |x, y| *noise.get_pixel(if x < 32 { x } else { 63 - x }, y));
+        for mode in [SymmetryMode::Pixel, SymmetryMode::Gradient] {
+            let symmetry = mirror_symmetry(&mirrored, MirrorAxis::Vertical, mode);
+            assert!(symmetry.score > 0.99, "score {}", symmetry.score);
+            assert!((symmetry.axis - 0.5).abs() < 1e-6, "axis {}", symmetry.axis);
+        }
+
+        // the same image turned on its side has a horizontal axis
+        let turned = image::imageops::rotate90(&mirrored);
+        let symmetry = mirror_symmetry(&turned, MirrorAxis::Horizontal, SymmetryMode::Pixel);
+        assert!(symmetry.score > 0.99 && (symmetry.axis - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn noise_is_not_symmetric() {
+        let noise = noise_image(64, 64, 2);
+        assert!(mirror_symmetry(&noise, MirrorAxis::Vertical, SymmetryMode::Pixel).score < 0.3);
+        let rotational = rotational_symmetry(&noise, SymmetryMode::Pixel);
+        assert!(rotational.order_2.abs() < 0.1 && rotational.order_4.abs() < 0.1);
+    }
+
+    #[test]
+    fn rotated_copies_are_rotationally_symmetric() {
+        // one quadrant of noise repeated with 90 degree turns
+        let quadrant = noise_image(16, 16, 3);
+        let pinwheel = GrayImage::from_fn(32, 32, |x, y| {
+            let (x, y) = match (x < 16, y < 16) {
+                (true, true) => (x, y),
+                (false, true) => (y, 31 - x),
+                (false, false) => (31 - x, 31 - y),
+                (true, false) => (31 - y, x),
+            };
+            *quadrant.get_pixel(x, y)
+        });
+        for mode in [SymmetryMode::Pixel, SymmetryMode::Gradient] {
+            let symmetry = rotational_symmetry(&pinwheel, mode);
+            assert!(symmetry.order_2 > 0.99 && symmetry.order_4 > 0.99, "{} {}", symmetry.order_2, symmetry.order_4);
+        }
+    }
+
+    #[test]
+    fn entropy_of_constant_and_uniform_images() {
+        let flat = GrayImage::from_pixel(16, 16, Luma([77]));
+        assert_eq!(gray_entropy(&flat, None), 0.0);
+        assert_eq!(entropy_2d(&flat), 0.0);
+
+        // every gray level exactly once
+        let uniform = GrayImage::from_fn(16, 16, |x, y| Luma([(y * 16 + x) as u8]));
+        assert!((gray_entropy(&uniform, None) - 8.0).abs() < 1e-5);
+
+        let channels = image::RgbImage::from_fn(16, 16, |x, y| Rgb([(y * 16 + x) as u8, 0, (x % 2) as u8]));
+        let entropy = channel_entropy(&channels);
+        assert!((entropy[0] - 8.0).abs() < 1e-5 && entropy[1] == 0.0 && (entropy[2] - 1.0).abs() < 1e-5);
+
+        assert_eq!(entropy_of_histogram(&[]), 0.0);
+        assert_eq!(entropy_of_histogram(&[3, 0, 3]), 1.0);
+    }
+
+    #[test]
+    fn entropy_2d_is_at_least_the_gray_entropy() {
+        // the joint entropy of (level, neighbourhood mean) can not be below the entropy of the level
+        let noise = noise_image(64, 64, 4);
+        let (h1, h2) = (gray_entropy(&noise, None), entropy_2d(&noise));
+        assert!(h2 >= h1 && h2 <= 16.0, "{} {}", h1, h2);
+    }
+
+    #[test]
+    fn local_entropy_of_a_checkerboard() {
+        let checkerboard = GrayImage::from_fn(8, 8, |x, y| Luma([if (x + y) % 2 == 0 { 0 } else { 255 }]));
+        let map = local_entropy_map(&checkerboard, 3);
+        // 5 against 4 pixels of each color inside every 3 x 3 window away from the border
+        let expected = -(5.0f32 / 9.0 * (5.0f32 / 9.0).log2() + 4.0 / 9.0 * (4.0f32 / 9.0).log2());
+        assert!((map[(3, 4)] - expected).abs() < 1e-5, "{}", map[(3, 4)]);
+
+        let flat = local_entropy_map(&GrayImage::from_pixel(8, 8, Luma([9])), 5);
+        assert!(flat.values().all(|v| *v == 0.0));
+    }
+
+    #[test]
+    fn spatial_information_of_a_ramp() {
+        // a slope of 2 gray levels per pixel is a sobel response of 4 * 2 * 2 = 16 everywhere
+        let ramp = Plane::from_fn(16, 16, |x, _| 2.0 * x as f32);
+        let si = spatial_information_from_gradients(&convolve_3x3(&ramp, &SOBEL_X), &convolve_3x3(&ramp, &SOBEL_Y), None);
+        assert!((si.mean - 16.0).abs() < 1e-4 && (si.rms - 16.0).abs() < 1e-4);
+        assert!(si.std_dev < 1e-2, "{}", si.std_dev);
+    }
+
+    #[test]
+    fn texture_metrics_do_not_depend_on_the_thread_count() {
+        let pixels = gray_to_plane(&noise_image(48, 40, 5)).map(|v| v as u8);
+        let run = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| (coarseness(&pixels, None), directionality(&pixels, 0.12, 16, None)))
+        };
+        assert_eq!(run(1), run(4));
+    }
+}
@@ -0,0 +1,240 @@
+use std::io::Cursor;
+use std::path::Path;
+use exif::{Exif, In, Tag, Value};
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageFormat};
+use crate::hdr::decode_image;
+use crate::icc::{profile_description, MatrixProfile};
+
+// LOADING
+// decoders hand out the stored pixels. Cameras and phones store them in sensor orientation with
+// an EXIF tag saying how to turn them, and wide gamut files carry an ICC profile saying what the
+// numbers mean. Both are applied here so every metric sees the image upright and in sRGB.
+// The EXIF fields that explain an image's look (camera, focal length, ISO, exposure) are read
+// along with them so they can be written next to the features
+
+// TIFF tag of an embedded ICC profile, see the ICC specification annex B.4
+const TIFF_ICC_TAG: u16 = 34675;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoadOptions {
+    // turn the image upright according to its EXIF orientation
+    pub apply_orientation: bool,
+    // convert from the embedded ICC profile to sRGB
+    pub color_management: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions { apply_orientation: true, color_management: true }
+    }
+}
+
+// what the file says about the image besides its pixels, None where it says nothing
+#[derive(Clone, Debug, Default)]
+pub struct ImageMetadata {
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    // in millimeters
+    pub focal_length: Option<f32>,
+    pub iso: Option<u32>,
+    // in seconds
+    pub exposure_time: Option<f32>,
+    pub f_number: Option<f32>,
+    // EXIF orientation, 1 (upright) to 8
+    pub orientation: Option<u16>,
+    // description of the embedded ICC profile
+    pub icc_profile: Option<String>,
+}
+
+pub const METADATA_COLUMNS: [&str; 8] = [
+    "camera_make", "camera_model", "focal_length", "iso", "exposure_time", "f_number", "orientation", "icc_profile",
+];
+
+impl ImageMetadata {
+    // one field per METADATA_COLUMNS entry, empty when missing. Commas are replaced so the
+    // fields can go into a csv line as they are
+    pub fn csv_fields(&self) -> Vec<String> {
+        fn field<T: ToString>(value: &Option<T>) -> String {
+            value.as_ref().map_or(String::new(), |value| value.to_string().replace(',', ";"))
+        }
+        vec![
+            field(&self.camera_make),
+            field(&self.camera_model),
+            field(&self.focal_length),
+            field(&self.iso),
+            field(&self.exposure_time),
+            field(&self.f_number),
+            field(&self.orientation),
+            field(&self.icc_profile),
+        ]
+    }
+}
+
+pub struct LoadedImage {
+    pub image: DynamicImage,
+    pub metadata: ImageMetadata,
+    // whether the pixels were converted from the embedded profile
+    pub converted_to_srgb: bool,
+}
+
+fn exif_ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(strings) => {
+            let text = String::from_utf8_lossy(strings.first()?);
+            let text = text.trim_end_matches('\0').trim();
+            (!text.is_empty()).then(|| text.to_string())
+        }
+        _ => None,
+    }
+}
+
+fn exif_rational(exif: &Exif, tag: Tag) -> Option<f32> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) if values.first()?.denom != 0 => Some(values.first()?.to_f64() as f32),
+        _ => None,
+    }
+}
+
+fn exif_uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+// the raw ICC profile of a jpeg, png or tiff file
+fn embedded_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    match image::guess_format(bytes).ok()? {
+        ImageFormat::Jpeg => JpegDecoder::new(Cursor::new(bytes)).ok()?.icc_profile(),
+        ImageFormat::Png => PngDecoder::new(Cursor::new(bytes)).ok()?.icc_profile(),
+        ImageFormat::Tiff => tiff::decoder::Decoder::new(Cursor::new(bytes)).ok()?
+            .get_tag_u8_vec(tiff::tags::Tag::Unknown(TIFF_ICC_TAG))
+            .ok(),
+        _ => None,
+    }
+}
+
+// EXIF fields and profile name without decoding the pixels
+pub fn read_metadata(bytes: &[u8]) -> ImageMetadata {
+    metadata_with_profile(bytes, embedded_profile(bytes).as_deref())
+}
+
+// read_metadata() with the embedded profile already extracted
+fn metadata_with_profile(bytes: &[u8], profile: Option<&[u8]>) -> ImageMetadata {
+    let mut metadata = ImageMetadata {
+        icc_profile: profile.and_then(profile_description),
+        ..ImageMetadata::default()
+    };
+    let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(bytes)) else {
+        return metadata;
+    };
+    metadata.camera_make = exif_ascii(&exif, Tag::Make);
+    metadata.camera_model = exif_ascii(&exif, Tag::Model);
+    metadata.focal_length = exif_rational(&exif, Tag::FocalLength);
+    metadata.iso = exif_uint(&exif, Tag::PhotographicSensitivity);
+    metadata.exposure_time = exif_rational(&exif, Tag::ExposureTime);
+    metadata.f_number = exif_rational(&exif, Tag::FNumber);
+    metadata.orientation = exif_uint(&exif, Tag::Orientation).map(|v| v as u16);
+    metadata
+}
+
+// the stored image turned upright, orientation values as in EXIF 2.32 table 4 (the first row /
+// column of the stored image is at the named side of the displayed one)
+pub fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        // top right
+        2 => image.fliph(),
+        // bottom right
+        3 => image.rotate180(),
+        // bottom left
+        4 => image.flipv(),
+        // left top, a transpose
+        5 => image.rotate90().fliph(),
+        // right top
+        6 => image.rotate90(),
+        // right bottom, a transverse
+        7 => image.rotate270().fliph(),
+        // left bottom
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+pub fn load_image(bytes: &[u8], options: &LoadOptions) -> Result<LoadedImage, ImageError> {
+    let mut image = decode_image(bytes)?;
+    let profile = embedded_profile(bytes);
+    let metadata = metadata_with_profile(bytes, profile.as_deref());
+
+    let mut converted_to_srgb = false;
+    if options.color_management {
+        if let Some(profile) = profile.as_deref().and_then(MatrixProfile::parse) {
+            if !profile.is_srgb() {
+                converted_to_srgb = profile.convert_to_srgb(&mut image);
+            }
+        }
+    }
+    if options.apply_orientation {
+        image = apply_orientation(image, metadata.orientation.unwrap_or(1));
+    }
+    Ok(LoadedImage { image, metadata, converted_to_srgb })
+}
+
+pub fn open_image(path: &Path, options: &LoadOptions) -> Result<LoadedImage, ImageError> {
+    load_image(&std::fs::read(path)?, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icc::tests::{profile_bytes, srgb_curve, srgb_profile, ADOBE_RGB_D50};
+
+    // a png with the given profile in its iCCP chunk
+    fn tagged_png(image: &image::RgbImage, profile: Vec<u8>) -> Vec<u8> {
+        let mut info = png::Info::with_size(image.width(), image.height());
+        info.color_type = png::ColorType::Rgb;
+        info.bit_depth = png::BitDepth::Eight;
+        info.icc_profile = Some(profile.into());
+        let mut bytes = Vec::new();
+        let mut writer = png::Encoder::with_info(&mut bytes, info).unwrap().write_header().unwrap();
+        writer.write_image_data(image.as_raw()).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn orientations_turn_the_image_upright() {
+        // 2 wide, 3 high, rows [0, 1], [2, 3], [4, 5]
+        let stored = DynamicImage::ImageLuma8(image::GrayImage::from_fn(2, 3, |x, y| image::Luma([(x + 2 * y) as u8])));
+        let expected: [(u16, u32, &[u8]); 8] = [
+            (1, 2, &[0, 1, 2, 3, 4, 5]),
+            (2, 2, &[1, 0, 3, 2, 5, 4]),
+            (3, 2, &[5, 4, 3, 2, 1, 0]),
+            (4, 2, &[4, 5, 2, 3, 0, 1]),
+            (5, 3, &[0, 2, 4, 1, 3, 5]),
+            (6, 3, &[4, 2, 0, 5, 3, 1]),
+            (7, 3, &[5, 3, 1, 4, 2, 0]),
+            (8, 3, &[1, 3, 5, 0, 2, 4]),
+        ];
+        for (orientation, width, pixels) in expected {
+            let upright = apply_orientation(stored.clone(), orientation).into_luma8();
+            assert_eq!(upright.dimensions(), (width, 6 / width), "orientation {}", orientation);
+            assert_eq!(upright.as_raw().as_slice(), pixels, "orientation {}", orientation);
+        }
+    }
+
+    #[test]
+    fn srgb_profiles_are_left_unconverted() {
+        let image = image::RgbImage::from_fn(4, 4, |x, y| image::Rgb([(x * 60) as u8, (y * 60) as u8, 200]));
+        let bytes = tagged_png(&image, srgb_profile("sRGB IEC61966-2.1"));
+        let loaded = load_image(&bytes, &LoadOptions::default()).unwrap();
+        assert!(!loaded.converted_to_srgb);
+        assert_eq!(loaded.image.into_rgb8(), image);
+        assert_eq!(loaded.metadata.icc_profile.as_deref(), Some("sRGB IEC61966-2.1"));
+
+        let bytes = tagged_png(&image, profile_bytes(&ADOBE_RGB_D50, &srgb_curve(), "Adobe RGB"));
+        assert!(load_image(&bytes, &LoadOptions::default()).unwrap().converted_to_srgb);
+        let unmanaged = LoadOptions { color_management: false, ..LoadOptions::default() };
+        let loaded = load_image(&bytes, &unmanaged).unwrap();
+        assert!(!loaded.converted_to_srgb);
+        assert_eq!(read_metadata(&bytes).icc_profile.as_deref(), Some("Adobe RGB"));
+    }
+}
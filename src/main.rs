@@ -1,25 +1,218 @@
 mod image_process;
 mod utils;
+mod plane;
+mod simd;
 mod colorfulness;
+mod noise;
+mod quality;
+mod artifacts;
+mod tonal;
+mod saliency;
+mod composition;
+mod complexity;
+mod fractal;
+mod quadtree;
+mod spectrum;
+mod metrics;
+mod context;
+mod tiling;
+mod mask;
+mod pyramid;
+mod preprocess;
+mod hdr;
+mod icc;
+mod loader;
+mod batch;
+mod feature_cache;
+mod streaming;
 
 use std::io::Cursor;
-use image::io::Reader as ImageReader;
-use image_process::directionality;
+use image_process::{directionality, mirror_symmetry, rotational_symmetry, MirrorAxis, SymmetryMode};
+use image_process::{channel_entropy, entropy_2d, gray_entropy, local_entropy_map, spatial_information_from_gradients};
 
-use crate::colorfulness::{lab_to_rgb, lab_to_rgb_image, colorfulness_metrics_1_3, rgb_to_lab_image, colorfulness_metrics_2, lab_saturation, grayscale_sd, grayscale, count_unique_colors, posterize};
+use crate::colorfulness::{lab_to_rgb, lab_to_rgb_image, colorfulness_metrics_1_3, colorfulness_metrics_2, lab_saturation, grayscale_sd, count_unique_colors, posterize, Luminance};
 use crate::image_process::{coarseness, edge_pixels_ratio, sobel_convolution};
+use crate::noise::{estimate_noise, estimate_noise_per_channel};
+use crate::quality::brisque_features;
+use crate::artifacts::{blockiness, estimate_jpeg_quality_from_file, ringing};
+use crate::tonal::{luminance_histogram, save_histogram_csv, tonal_features};
+use crate::saliency::{center_surround, frequency_tuned, saliency_mask, saliency_summary, spectral_residual, DEFAULT_MASK_FACTOR};
+use crate::composition::{composition_features, edge_weights, CompositionFeatures};
+use crate::complexity::{compression_complexity, DEFAULT_JPEG_QUALITY};
+use crate::fractal::{binary_from_gray, box_counting, differential_box_counting};
+use crate::quadtree::{quadtree_gray, quadtree_lab, quadtree_visualization, Homogeneity};
+use crate::spectrum::spectral_features;
+use crate::metrics::Metric;
+use crate::tiling::{feature_map, Tiling};
+use crate::mask::Mask;
+use crate::batch::{csv_header, image_paths, run_batch, BatchOptions};
+use crate::feature_cache::{FeatureCache, DEFAULT_CACHE_FILE};
+use crate::preprocess::{filter_from_name, preprocess, CanonicalSize, Framing, ResizePolicy};
+use crate::hdr::ToneMapping;
+use crate::loader::{open_image, LoadOptions};
+use crate::pyramid::{gaussian_pyramid, laplacian_pyramid, laplacian_visualization, metric_at_canonical_resolution, metric_per_scale};
 use crate::utils::{normalize_value, save_to_image, save_to_image_f32};
+use crate::context::AnalysisContext;
+use crate::streaming::{analyze_streaming, DEFAULT_STRIP_ROWS};
+
+// value of a "--name=value" command line argument
+fn arg_value(name: &str) -> Option<String> {
+    let prefix = format!("--{}=", name);
+    std::env::args().find_map(|arg| arg.strip_prefix(&prefix).map(|value| value.to_string()))
+}
+
+// comma separated metric names
+fn metric_list(names: &str) -> Vec<Metric> {
+    names.split(',').map(|name| Metric::from_name(name).expect("unknown metric")).collect()
+}
 
 fn main() {
-    let image = ImageReader::open("res/urban.jpg").unwrap().decode().unwrap();
+    // --threads=N limits the worker threads, all cores are used otherwise
+    if let Some(threads) = arg_value("threads") {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.parse().expect("--threads=N"))
+            .build_global()
+            .unwrap();
+    }
+
+    // every gray input below is derived with this method, pick another one with --luminance=<name>
+    let luminance = arg_value("luminance")
+        .map(|name| Luminance::from_name(&name).expect("unknown luminance method"))
+        .unwrap_or(Luminance::Rec709);
+
+    // --resize=longest:N|shortest:N|megapixels:M, --resize-filter=<name> and
+    // --framing=full|crop:ASPECT|letterbox:ASPECT, without them the image is used as decoded.
+    // --tone-map=reinhard|exposure[:STOPS]|clip picks how float (hdr, exr) input is brought to sRGB
+    let default_policy = ResizePolicy::default();
+    let policy = ResizePolicy {
+        size: arg_value("resize").map(|size| CanonicalSize::parse(&size).expect("unknown --resize")),
+        filter: arg_value("resize-filter")
+            .map(|name| filter_from_name(&name).expect("unknown filter"))
+            .unwrap_or(default_policy.filter),
+        framing: arg_value("framing")
+            .map(|framing| Framing::parse(&framing).expect("unknown --framing"))
+            .unwrap_or(default_policy.framing),
+        tone_mapping: arg_value("tone-map")
+            .map(|mapping| ToneMapping::parse(&mapping).expect("unknown --tone-map"))
+            .unwrap_or(default_policy.tone_mapping),
+    };
+
+    // --exif-orientation=off keeps the stored orientation, --color-management=off keeps the
+    // samples as stored instead of converting them from the embedded ICC profile to sRGB
+    let load = LoadOptions {
+        apply_orientation: arg_value("exif-orientation").is_none_or(|value| value != "off"),
+        color_management: arg_value("color-management").is_none_or(|value| value != "off"),
+    };
+
+    // batch runs keep their values in a feature cache, --cache=FILE moves it and --cache=off
+    // turns it off. --cache-action=inspect|prune|invalidate works on the cache and exits,
+    // invalidate drops the --metrics=a,b,c values (all by default) of --image=FILE (all images
+    // by default)
+    let cache_file = arg_value("cache").unwrap_or(DEFAULT_CACHE_FILE.to_string());
+    if let Some(action) = arg_value("cache-action") {
+        let mut cache = FeatureCache::open(std::path::Path::new(&cache_file)).unwrap();
+        match action.as_str() {
+            "inspect" => {
+                let summary = cache.summary();
+                println!("------- Feature Cache -------");
+                println!("file: {}", cache.file().display());
+                if cache.is_empty() {
+                    println!("empty, batch runs fill it");
+                }
+                println!("{} values of {} images", summary.entries, summary.images);
+                for (metric, count, parameters) in summary.metrics {
+                    println!("{}: {} values, {} parameter sets", metric, count, parameters);
+                }
+            }
+            "prune" => println!("pruned {} values, {} left", cache.prune().unwrap(), cache.len()),
+            "invalidate" => {
+                let metrics = arg_value("metrics").map(|names| metric_list(&names)).unwrap_or_default();
+                let image = arg_value("image");
+                let removed = cache.invalidate(&metrics, image.as_ref().map(std::path::Path::new)).unwrap();
+                println!("invalidated {} values, {} left", removed, cache.len());
+            }
+            _ => panic!("--cache-action=inspect|prune|invalidate"),
+        }
+        return;
+    }
+
+    // --batch=DIR analyzes every image in DIR instead of the single image below, one csv row per
+    // file. --metrics=a,b,c picks the columns (all metrics by default)
+    if let Some(directory) = arg_value("batch") {
+        let metrics = arg_value("metrics").map(|names| metric_list(&names)).unwrap_or(Metric::ALL.to_vec());
+        let options = BatchOptions {
+            metrics,
+            luminance,
+            policy,
+            load,
+            chunk_size: rayon::current_num_threads() * 2,
+        };
+        let output = arg_value("batch-output").unwrap_or("res/output/batch.csv".to_string());
+        let mut lines = vec![csv_header(&options.metrics)];
+        let paths = image_paths(std::path::Path::new(&directory)).unwrap();
+        let mut cache = match cache_file.as_str() {
+            "off" => None,
+            file => Some(FeatureCache::open(std::path::Path::new(file)).unwrap()),
+        };
+        let mut cached_values = 0;
+        run_batch(&paths, &options, cache.as_mut(), |path, row| match row {
+            Ok(row) => {
+                let cached = row.from_cache.iter().filter(|from_cache| **from_cache).count();
+                println!("{} ({} of {} values cached)", path.display(), cached, row.values.len());
+                cached_values += cached;
+                lines.push(row.csv_line(&options));
+            }
+            Err(error) => eprintln!("skipping {}: {}", path.display(), error),
+        }).unwrap();
+        if let Some(parent) = std::path::Path::new(&output).parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(&output, lines.join("\n") + "\n").unwrap();
+        println!("{} of {} images written to {}, {} values from the cache", lines.len() - 1, paths.len(), output, cached_values);
+        return;
+    }
+
+    // --stream=FILE analyzes a png or tiff too large to decode at once, --strip-rows=N rows at a
+    // time. Only the metrics that can be accumulated strip by strip are reported, EXIF orientation
+    // and ICC profiles are not applied
+    if let Some(path) = arg_value("stream") {
+        let strip_rows = arg_value("strip-rows").map(|n| n.parse().expect("--strip-rows=N")).unwrap_or(DEFAULT_STRIP_ROWS);
+        let report = analyze_streaming(std::path::Path::new(&path), luminance, strip_rows).unwrap();
+        println!("------- Streaming -------");
+        println!("image: {} ({}x{}), {} rows per strip", path, report.width, report.height, strip_rows);
+        for metric in Metric::ALL {
+            if let Some(value) = report.value(metric) {
+                println!("{}: {}", metric.name(), value);
+            }
+        }
+        println!("spatial information: mean {}, rms {}", report.spatial_information.mean, report.spatial_information.rms);
+        println!("edge density (sobel > {}): {}", crate::metrics::CANNY_HIGH, report.edge_density);
+        let stem = std::path::Path::new(&path).file_stem().unwrap().to_string_lossy().to_string();
+        std::fs::create_dir_all("res/output").unwrap();
+        save_histogram_csv(&report.histogram, &format!("res/output/{}_stream_histogram.csv", stem));
+        return;
+    }
+
+    // --image=FILE analyzes another image, outputs keep their urban_ names
+    let path = arg_value("image").unwrap_or("res/urban.jpg".to_string());
+    let path = path.as_str();
+    let loaded = open_image(std::path::Path::new(path), &load).unwrap();
+    let preprocessed = preprocess(&loaded.image, &policy);
+    let image = preprocessed.image;
 
     let image_f32 = image.to_rgb32f();
 
-    let image_u8 = image.to_rgb8();
+    // grayscale, cieLAB, edges etc. are built once here and shared by everything below
+    let context = AnalysisContext::with_precision(&image_f32, luminance, preprocessed.input.high_precision());
+
+    // transparent pixels, e.g. letterbox bars, are left out of the metrics that take a mask
+    let opaque = Mask::from_alpha(&image);
 
-    let image_grayscale = image.to_luma8();
+    let image_u8 = context.rgb8();
 
-    let image_lab = rgb_to_lab_image(&image_f32);
+    let image_grayscale = context.gray_image();
+
+    let image_lab = context.lab();
 
     // let urban_coarseness = coarseness(&urban_grayscale);
     // println!("calculated coarseness of urban image");
@@ -36,7 +229,7 @@ fn main() {
     // println!("\n------- Metric Two -------");
     // println!("urban: {}", urban_met_2);
 
-    // let urban_grayscale_sd = grayscale_sd(grayscale(&urban_p));
+    // let urban_grayscale_sd = grayscale_sd(grayscale(&urban_p, luminance));
 
     // println!("\n------- GrayscaleSD -------");
     // println!("urban grayscale sd: {}", urban_grayscale_sd);
@@ -54,15 +247,250 @@ fn main() {
     
     // urban_blurred.save("res/output/urban_blurred.png").unwrap();
     
-    //let edged = imageproc::edges::canny(&image_grayscale, 1.0, 27.0);
+    //let edged = imageproc::edges::canny(image_grayscale, 1.0, 27.0);
     
     // let urban_edge_density = edge_pixels_ratio(urban_edged);
     
     // println!("\n------- Edge Density -------");
     // println!("urban edge density: {}", urban_edge_density);
 
-    let dir = directionality(&image_grayscale, 0.12, 16);
-    print!("dir: {}", dir);
+    println!("------- Metadata -------");
+    println!("image: {}", path);
+    println!("luminance: {}", luminance.name());
+    println!("original size: {}x{}", preprocessed.original.0, preprocessed.original.1);
+    println!("analysis size: {}x{}", preprocessed.analysis.0, preprocessed.analysis.1);
+    println!("resize: {:?}, framing: {:?}, filter: {:?}", policy.size, policy.framing, policy.filter);
+    let input = preprocessed.input;
+    if input.scene_linear {
+        println!("input: {}-bit float, scene linear, peak luminance {}, tone mapping: {:?}",
+            input.bits_per_channel, input.peak, policy.tone_mapping);
+    } else {
+        println!("input: {}-bit, display referred", input.bits_per_channel);
+    }
+    let metadata = &loaded.metadata;
+    match &metadata.icc_profile {
+        Some(profile) if loaded.converted_to_srgb => println!("icc profile: {} (converted to sRGB)", profile),
+        Some(profile) => println!("icc profile: {}", profile),
+        None => println!("icc profile: none"),
+    }
+    println!("orientation: {} (applied: {})", metadata.orientation.unwrap_or(1), load.apply_orientation);
+    let camera = [&metadata.camera_make, &metadata.camera_model].into_iter().flatten().cloned().collect::<Vec<_>>();
+    if !camera.is_empty() {
+        println!("camera: {}", camera.join(" "));
+    }
+    if let Some(focal_length) = metadata.focal_length {
+        println!("focal length: {} mm", focal_length);
+    }
+    if let Some(iso) = metadata.iso {
+        println!("iso: {}", iso);
+    }
+    if let Some(exposure_time) = metadata.exposure_time {
+        println!("exposure time: {} s", exposure_time);
+    }
+    if let Some(f_number) = metadata.f_number {
+        println!("f-number: {}", f_number);
+    }
+
+    let dir = directionality(context.gray_u8(), 0.12, 16, opaque.as_ref());
+    println!("\n------- Directionality -------");
+    println!("dir: {}", dir);
+
+    println!("\n------- Symmetry -------");
+    for mode in [SymmetryMode::Pixel, SymmetryMode::Gradient] {
+        let name = if mode == SymmetryMode::Pixel { "pixel" } else { "gradient" };
+        let vertical = mirror_symmetry(image_grayscale, MirrorAxis::Vertical, mode);
+        let horizontal = mirror_symmetry(image_grayscale, MirrorAxis::Horizontal, mode);
+        let rotational = rotational_symmetry(image_grayscale, mode);
+        println!("{}: vertical axis {} (at {}), horizontal axis {} (at {})",
+            name, vertical.score, vertical.axis, horizontal.score, horizontal.axis);
+        println!("{}: rotational 180 {}, rotational 90 {}", name, rotational.order_2, rotational.order_4);
+    }
+
+    let noise = estimate_noise(image_grayscale);
+    let noise_rgb = estimate_noise_per_channel(image_u8);
+
+    println!("\n------- Noise -------");
+    println!("gray sigma: immerkaer {}, pca {}", noise.immerkaer, noise.pca);
+    for (name, estimate) in ["r", "g", "b"].iter().zip(noise_rgb.iter()) {
+        println!("{} sigma: immerkaer {}, pca {}", name, estimate.immerkaer, estimate.pca);
+    }
+
+    let brisque = brisque_features(image_grayscale);
+
+    println!("\n------- BRISQUE -------");
+    println!("{:?}", brisque);
+
+    println!("\n------- Compression Artifacts -------");
+    println!("blockiness: {}", blockiness(image_grayscale));
+    println!("ringing: {}", ringing(image_grayscale));
+    match estimate_jpeg_quality_from_file(path) {
+        Some(quality) => println!("jpeg quality: {}", quality),
+        None => println!("jpeg quality: not a jpeg"),
+    }
+
+    let image_gray_plane = context.gray();
+    let tonal = tonal_features(image_gray_plane, luminance);
+
+    println!("\n------- Tonal -------");
+    println!("mean luminance: {}", tonal.mean);
+    for (p, value) in tonal.percentiles.iter() {
+        println!("p{}: {}", p, value);
+    }
+    println!("highlight clipping: {}, shadow clipping: {}", tonal.highlight_clipping, tonal.shadow_clipping);
+    println!("dynamic range: {} stops", tonal.dynamic_range_stops);
+    println!("skewness: {}, kurtosis: {}", tonal.skewness, tonal.kurtosis);
+    println!("rms contrast: {}, sd: {}", tonal.rms_contrast, tonal.std_dev);
+
+    std::fs::create_dir_all("res/output").unwrap();
+    save_histogram_csv(&luminance_histogram(image_gray_plane, 256), "res/output/urban_histogram.csv");
+
+    println!("\n------- Saliency -------");
+    let saliency_maps = [
+        ("spectral residual", spectral_residual(image_grayscale)),
+        ("frequency tuned", frequency_tuned(image_lab)),
+        ("center surround", center_surround(image_lab)),
+    ];
+    for (name, map) in saliency_maps.iter() {
+        let mask = saliency_mask(map, DEFAULT_MASK_FACTOR);
+        let summary = saliency_summary(map, &mask);
+        println!("{}: salient area {}, centroid {:?}", name, summary.area_ratio, summary.centroid);
+    }
+    saliency_mask(&saliency_maps[1].1, DEFAULT_MASK_FACTOR).save("res/output/urban_saliency_mask.png").unwrap();
+
+    println!("\n------- Composition -------");
+    let edged = context.edges();
+    let print_composition = |name: &str, features: CompositionFeatures| {
+        println!("{}: thirds line distance {}, power point distance {}, centroid to power point {}",
+            name, features.thirds_line_distance, features.power_point_distance, features.centroid_power_point_distance);
+        println!("{}: balance l/r {}, t/b {}, center offset {:?} ({})",
+            name, features.horizontal_balance, features.vertical_balance, features.center_offset, features.center_offset_distance);
+    };
+    print_composition("edges", composition_features(&edge_weights(edged)));
+    print_composition("saliency", composition_features(&saliency_maps[1].1));
+
+    println!("\n------- Complexity -------");
+    let compression = compression_complexity(image_u8, DEFAULT_JPEG_QUALITY);
+    println!("png: {} bpp (ratio {})", compression.png_bits_per_pixel, compression.png_ratio);
+    println!("jpeg q{}: {} bpp (ratio {})", DEFAULT_JPEG_QUALITY, compression.jpeg_bits_per_pixel, compression.jpeg_ratio);
+    println!("edge density: {}", edge_pixels_ratio(edged, opaque.as_ref()));
+    println!("unique colors: {}", count_unique_colors(image_u8, opaque.as_ref()));
+
+    println!("\n------- Fractal Dimension -------");
+    let edge_fractal = box_counting(&binary_from_gray(edged));
+    println!("edges box counting: {} (r2 {}), counts {:?}", edge_fractal.dimension, edge_fractal.r_squared, edge_fractal.counts);
+    let gray_fractal = differential_box_counting(image_grayscale);
+    println!("differential box counting: {} (r2 {}), counts {:?}", gray_fractal.dimension, gray_fractal.r_squared, gray_fractal.counts);
+
+    println!("\n------- Quadtree -------");
+    let gray_tree = quadtree_gray(image_gray_plane, Homogeneity::Variance(0.001), 4);
+    println!("gray: {} leaves, depth histogram {:?}", gray_tree.leaf_count(), gray_tree.depth_histogram);
+    let lab_tree = quadtree_lab(image_lab, Homogeneity::Range(10.0), 4);
+    println!("lab: {} leaves, depth histogram {:?}", lab_tree.leaf_count(), lab_tree.depth_histogram);
+    quadtree_visualization(&gray_tree, image_grayscale.width(), image_grayscale.height())
+        .save("res/output/urban_quadtree.png").unwrap();
+
+    println!("\n------- Entropy -------");
+    println!("gray entropy: {}", gray_entropy(image_grayscale, opaque.as_ref()));
+    println!("channel entropy: {:?}", channel_entropy(image_u8));
+    println!("2d entropy: {}", entropy_2d(image_grayscale));
+    let local_entropy = local_entropy_map(image_grayscale, 9);
+    let local_entropy_mean = local_entropy.mean();
+    println!("mean local entropy (9x9): {}", local_entropy_mean);
+    image::GrayImage::from_fn(image_grayscale.width(), image_grayscale.height(), |x, y| {
+        image::Luma([(local_entropy[(x as usize, y as usize)] / 8.0 * 255.0) as u8])
+    }).save("res/output/urban_local_entropy.png").unwrap();
+    let gradients = context.gradients();
+    let si = spatial_information_from_gradients(&gradients.x, &gradients.y, opaque.as_ref());
+    println!("spatial information: {} (mean {}, rms {})", si.std_dev, si.mean, si.rms);
+
+    println!("\n------- Power Spectrum -------");
+    let spectral = spectral_features(image_grayscale);
+    println!("1/f slope: {} (r2 {})", spectral.slope, spectral.slope_r_squared);
+    println!("high / low frequency energy: {}", spectral.high_low_ratio);
+    println!("angular energy: {:?}", spectral.angular_energy);
+
+    // --pyramid-metrics=a,b,c picks the metrics run per scale, --pyramid-levels=N the depth
+    println!("\n------- Pyramid -------");
+    let pyramid_levels = arg_value("pyramid-levels").map(|n| n.parse().unwrap()).unwrap_or(4);
+    let pyramid_metrics = metric_list(&arg_value("pyramid-metrics").unwrap_or("edge_density,entropy".to_string()));
+    let pyramid = gaussian_pyramid(&image_f32, pyramid_levels, 32);
+    for metric in pyramid_metrics.iter() {
+        for scale in metric_per_scale(&pyramid, *metric, luminance) {
+            println!("{} level {} ({}x{}): {}", metric.name(), scale.level, scale.width, scale.height, scale.value);
+        }
+        let canonical = metric_at_canonical_resolution(&image_f32, *metric, luminance, 512);
+        println!("{} canonical ({}x{}): {}", metric.name(), canonical.width, canonical.height, canonical.value);
+    }
+    for (level, band) in laplacian_pyramid(image_gray_plane, pyramid_levels, 32).iter().enumerate() {
+        laplacian_visualization(band).save(format!("res/output/urban_laplacian_{}.png", level)).unwrap();
+    }
+
+    // --roi=X,Y,WIDTH,HEIGHT restricts the metrics below to a rectangle, --mask=FILE to the white
+    // pixels of a gray image (scaled to the image). Images with an alpha channel are restricted
+    // to their opaque part
+    let mask = match (arg_value("roi"), arg_value("mask")) {
+        (Some(roi), _) => {
+            let roi: Vec<u32> = roi.split(',').map(|v| v.parse().expect("--roi=X,Y,WIDTH,HEIGHT")).collect();
+            assert_eq!(roi.len(), 4, "--roi=X,Y,WIDTH,HEIGHT");
+            Some(Mask::from_rect(image_f32.width(), image_f32.height(), roi[0], roi[1], roi[2], roi[3]))
+        }
+        (None, Some(path)) => {
+            let pixels = image::open(path).unwrap().to_luma8();
+            let pixels = image::imageops::resize(&pixels, image_f32.width(), image_f32.height(), image::imageops::FilterType::Nearest);
+            Some(Mask::from_gray(&pixels))
+        }
+        (None, None) => opaque,
+    };
+    if let Some(mask) = &mask {
+        println!("\n------- Region -------");
+        println!("included pixels: {} of {}, bounding box {:?}",
+            mask.count(), mask.width * mask.height, mask.bounding_box());
+        for metric in [Metric::Colorfulness1, Metric::Colorfulness2, Metric::Colorfulness3,
+            Metric::UniqueColors, Metric::EdgeDensity, Metric::Directionality, Metric::Entropy,
+            Metric::GrayscaleSd, Metric::NoiseImmerkaer, Metric::SpatialInformation] {
+            println!("{}: {} (whole image {})", metric.name(),
+                metric.compute_with(&context, Some(mask)), metric.compute_with(&context, None));
+        }
+    }
 
+    // e.g. --tile-metric=coarseness --tiles=8x6 for a coarseness heatmap
+    println!("\n------- Tiles -------");
+    let tile_metric = arg_value("tile-metric")
+        .map(|name| Metric::from_name(&name).expect("unknown metric"))
+        .unwrap_or(Metric::EdgeDensity);
+    // --tiles=COLUMNSxROWS for a grid, --tile-window=SIZE,STRIDE for sliding windows
+    let tiling = match arg_value("tile-window") {
+        Some(window) => {
+            let (size, stride) = window.split_once(',').expect("--tile-window=SIZE,STRIDE");
+            Tiling::Sliding { size: size.parse().unwrap(), stride: stride.parse().unwrap() }
+        }
+        None => {
+            let (columns, rows) = arg_value("tiles")
+                .and_then(|grid| {
+                    let (columns, rows) = grid.split_once('x')?;
+                    Some((columns.parse().ok()?, rows.parse().ok()?))
+                })
+                .unwrap_or((8, 6));
+            Tiling::Grid { columns, rows }
+        }
+    };
+    let tile_map = feature_map(&image_f32, tiling, tile_metric, luminance, mask.as_ref());
+    println!("{} per tile ({}x{}): mean {}, variance {}, max {}",
+        tile_map.metric.name(), tile_map.columns, tile_map.rows, tile_map.mean(), tile_map.variance(), tile_map.max());
+    let max_tile = tile_map.tiles.iter().flatten()
+        .zip(tile_map.values.values())
+        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+        .map(|(tile, _)| *tile)
+        .unwrap();
+    println!("max at x {}, y {} ({}x{})", max_tile.x, max_tile.y, max_tile.width, max_tile.height);
+    for row in tile_map.values.rows() {
+        println!("{:?}", row);
+    }
+    tile_map.heatmap(image_f32.width(), image_f32.height())
+        .save(format!("res/output/urban_{}_tiles.png", tile_metric.name())).unwrap();
 
+    println!("\n------- Intermediates -------");
+    for built in context.built().iter() {
+        println!("{}: {:.1} ms", built.name, built.duration.as_secs_f64() * 1000.0);
+    }
 }
@@ -0,0 +1,131 @@
+use image::{DynamicImage, GrayImage};
+
+// REGION OF INTEREST MASKS
+// metrics given a mask only look at the included pixels and normalize by the included area
+
+pub struct Mask {
+    pub width: u32,
+    pub height: u32,
+    // row major, true for included pixels
+    data: Vec<bool>,
+}
+
+impl Mask {
+    // everything inside the rectangle is included, the rectangle is clipped to the image
+    pub fn from_rect(width: u32, height: u32, x: u32, y: u32, rect_width: u32, rect_height: u32) -> Mask {
+        let mut data = vec![false; (width * height) as usize];
+        for yy in y.min(height)..(y.saturating_add(rect_height)).min(height) {
+            for xx in x.min(width)..(x.saturating_add(rect_width)).min(width) {
+                data[(yy * width + xx) as usize] = true;
+            }
+        }
+        Mask { width, height, data }
+    }
+
+    // white (> 127) pixels are included
+    pub fn from_gray(pixels: &GrayImage) -> Mask {
+        Mask {
+            width: pixels.width(),
+            height: pixels.height(),
+            data: pixels.pixels().map(|pix| pix[0] > 127).collect(),
+        }
+    }
+
+    // pixels that are more than half opaque are included, None if the image has no alpha channel
+    pub fn from_alpha(image: &DynamicImage) -> Option<Mask> {
+        if !image.color().has_alpha() {
+            return None;
+        }
+        let rgba = image.to_rgba8();
+        Some(Mask {
+            width: rgba.width(),
+            height: rgba.height(),
+            data: rgba.pixels().map(|pix| pix[3] > 127).collect(),
+        })
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x < self.width && y < self.height && self.data[(y * self.width + x) as usize]
+    }
+
+    // number of included pixels
+    pub fn count(&self) -> usize {
+        self.data.iter().filter(|included| **included).count()
+    }
+
+    // smallest rectangle holding every included pixel as (x, y, width, height), None when empty
+    pub fn bounding_box(&self) -> Option<(u32, u32, u32, u32)> {
+        let mut min = (u32::MAX, u32::MAX);
+        let mut max = (0, 0);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.contains(x, y) {
+                    min = (min.0.min(x), min.1.min(y));
+                    max = (max.0.max(x), max.1.max(y));
+                }
+            }
+        }
+        if min.0 == u32::MAX {
+            return None;
+        }
+        Some((min.0, min.1, max.0 - min.0 + 1, max.1 - min.1 + 1))
+    }
+
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Mask {
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for yy in y..(y + height) {
+            for xx in x..(x + width) {
+                data.push(self.contains(xx, yy));
+            }
+        }
+        Mask { width, height, data }
+    }
+}
+
+// whether the pixel takes part in a computation, every pixel does without a mask
+pub fn included(mask: Option<&Mask>, x: u32, y: u32) -> bool {
+    mask.is_none_or(|mask| mask.contains(x, y))
+}
+
+// whether the size x size window with its top left corner at (x, y) lies completely inside the
+// mask, for values computed from a neighbourhood (convolutions) that would otherwise see the
+// border of the region as structure
+pub fn window_included(mask: Option<&Mask>, x: u32, y: u32, size: u32) -> bool {
+    mask.is_none_or(|mask| (y..y + size).all(|yy| (x..x + size).all(|xx| mask.contains(xx, yy))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rectangle_is_clipped_to_the_image() {
+        let mask = Mask::from_rect(8, 6, 5, 4, 10, 10);
+        assert_eq!(mask.count(), 3 * 2);
+        assert_eq!(mask.bounding_box(), Some((5, 4, 3, 2)));
+        assert_eq!(Mask::from_rect(8, 6, 9, 9, 2, 2).bounding_box(), None);
+    }
+
+    #[test]
+    fn gray_mask_includes_white_pixels() {
+        let pixels = GrayImage::from_fn(4, 1, |x, _| image::Luma([[0, 127, 128, 255][x as usize]]));
+        let mask = Mask::from_gray(&pixels);
+        assert_eq!((0..4).map(|x| mask.contains(x, 0)).collect::<Vec<bool>>(), vec![false, false, true, true]);
+    }
+
+    #[test]
+    fn windows_must_lie_inside_the_mask() {
+        let mask = Mask::from_rect(8, 8, 2, 2, 4, 4);
+        assert!(window_included(Some(&mask), 2, 2, 3));
+        assert!(window_included(Some(&mask), 3, 3, 3));
+        // one column sticks out on the right
+        assert!(!window_included(Some(&mask), 4, 2, 3));
+        assert!(window_included(None, 7, 7, 3));
+    }
+
+    #[test]
+    fn crop_keeps_the_mask_aligned() {
+        let mask = Mask::from_rect(8, 8, 2, 2, 4, 4).crop(4, 4, 4, 4);
+        assert_eq!(mask.bounding_box(), Some((0, 0, 2, 2)));
+    }
+}
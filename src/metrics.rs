@@ -0,0 +1,224 @@
+use image::Rgb32FImage;
+use crate::colorfulness::{colorfulness_metrics_1_3, colorfulness_metrics_2, count_unique_colors, grayscale_sd, Luminance};
+use crate::complexity::{jpeg_bits_per_pixel, png_bits_per_pixel, DEFAULT_JPEG_QUALITY};
+use crate::context::AnalysisContext;
+use crate::fractal::{binary_from_gray, box_counting};
+use crate::image_process::{coarseness, directionality, edge_pixels_ratio, gray_entropy, spatial_information_from_gradients};
+use crate::noise::{immerkaer_sigma, pca_sigma};
+use crate::artifacts::blockiness;
+use crate::mask::Mask;
+use crate::spectrum::spectral_features;
+
+// SCALAR METRICS
+// every metric that boils an image down to a single number, selectable by name so it can be
+// run per tile, per pyramid level etc.
+
+// parameters the metrics are run with, same as in main
+pub const DIRECTIONALITY_THRESHOLD: f32 = 0.12;
+pub const DIRECTIONALITY_BINS: i32 = 16;
+pub const CANNY_LOW: f32 = 1.0;
+pub const CANNY_HIGH: f32 = 27.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    Coarseness,
+    Directionality,
+    Colorfulness1,
+    Colorfulness2,
+    Colorfulness3,
+    GrayscaleSd,
+    UniqueColors,
+    EdgeDensity,
+    NoiseImmerkaer,
+    NoisePca,
+    Blockiness,
+    Entropy,
+    SpatialInformation,
+    SpectralSlope,
+    PngBitsPerPixel,
+    JpegBitsPerPixel,
+    FractalDimension,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 17] = [
+        Metric::Coarseness,
+        Metric::Directionality,
+        Metric::Colorfulness1,
+        Metric::Colorfulness2,
+        Metric::Colorfulness3,
+        Metric::GrayscaleSd,
+        Metric::UniqueColors,
+        Metric::EdgeDensity,
+        Metric::NoiseImmerkaer,
+        Metric::NoisePca,
+        Metric::Blockiness,
+        Metric::Entropy,
+        Metric::SpatialInformation,
+        Metric::SpectralSlope,
+        Metric::PngBitsPerPixel,
+        Metric::JpegBitsPerPixel,
+        Metric::FractalDimension,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Coarseness => "coarseness",
+            Metric::Directionality => "directionality",
+            Metric::Colorfulness1 => "colorfulness_1",
+            Metric::Colorfulness2 => "colorfulness_2",
+            Metric::Colorfulness3 => "colorfulness_3",
+            Metric::GrayscaleSd => "grayscale_sd",
+            Metric::UniqueColors => "unique_colors",
+            Metric::EdgeDensity => "edge_density",
+            Metric::NoiseImmerkaer => "noise_immerkaer",
+            Metric::NoisePca => "noise_pca",
+            Metric::Blockiness => "blockiness",
+            Metric::Entropy => "entropy",
+            Metric::SpatialInformation => "spatial_information",
+            Metric::SpectralSlope => "spectral_slope",
+            Metric::PngBitsPerPixel => "png_bpp",
+            Metric::JpegBitsPerPixel => "jpeg_bpp",
+            Metric::FractalDimension => "fractal_dimension",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Metric> {
+        Metric::ALL.iter().copied().find(|metric| metric.name() == name)
+    }
+
+    // whether the metric can skip excluded pixels itself. The others (pca noise, blockiness,
+    // spectral slope, compression and fractal dimension need the whole image or its block grid)
+    // only honor the bounding box of the mask: they run on that rectangle, excluded pixels
+    // inside it included
+    pub fn supports_mask(&self) -> bool {
+        matches!(
+            self,
+            Metric::Coarseness
+                | Metric::Directionality
+                | Metric::Colorfulness1
+                | Metric::Colorfulness2
+                | Metric::Colorfulness3
+                | Metric::GrayscaleSd
+                | Metric::UniqueColors
+                | Metric::EdgeDensity
+                | Metric::NoiseImmerkaer
+                | Metric::Entropy
+                | Metric::SpatialInformation
+        )
+    }
+
+    // the constants the metric is computed with, part of the feature cache key so changing one
+    // recomputes the metric
+    pub fn parameters(&self) -> String {
+        match self {
+            Metric::Directionality => format!("threshold={},bins={}", DIRECTIONALITY_THRESHOLD, DIRECTIONALITY_BINS),
+            Metric::EdgeDensity | Metric::FractalDimension => format!("canny={},{}", CANNY_LOW, CANNY_HIGH),
+            Metric::JpegBitsPerPixel => format!("quality={}", DEFAULT_JPEG_QUALITY),
+            _ => String::new(),
+        }
+    }
+
+    // gray inputs are derived with the given luminance method. With a mask the metric only
+    // looks at the included pixels (or their bounding box, see supports_mask()), an empty mask
+    // gives 0
+    pub fn compute(&self, image: &Rgb32FImage, luminance: Luminance, mask: Option<&Mask>) -> f32 {
+        self.compute_with(&AnalysisContext::new(image, luminance), mask)
+    }
+
+    // same as compute(), with the intermediates taken from (and left in) the context so
+    // several metrics on one image share them
+    pub fn compute_with(&self, context: &AnalysisContext, mask: Option<&Mask>) -> f32 {
+        if let Some(mask) = mask {
+            // an empty mask leaves nothing to measure
+            let Some((x, y, width, height)) = mask.bounding_box() else {
+                return 0.0;
+            };
+            if !self.supports_mask() {
+                // a box covering the whole image changes nothing, the shared context is kept
+                if (width, height) == context.image().dimensions() {
+                    return self.compute_with(context, None);
+                }
+                let view = image::imageops::crop_imm(context.image(), x, y, width, height).to_image();
+                return self.compute_with(&AnalysisContext::with_precision(&view, context.luminance(), context.full_precision()), None);
+            }
+        }
+
+        match self {
+            Metric::Coarseness => coarseness(context.gray_u8(), mask),
+            Metric::Directionality => directionality(context.gray_u8(), DIRECTIONALITY_THRESHOLD, DIRECTIONALITY_BINS, mask),
+            Metric::Colorfulness1 => colorfulness_metrics_1_3(context.lab(), mask).0,
+            Metric::Colorfulness2 => colorfulness_metrics_2(context.lab(), mask),
+            Metric::Colorfulness3 => colorfulness_metrics_1_3(context.lab(), mask).1,
+            Metric::GrayscaleSd => grayscale_sd(context.gray(), mask),
+            Metric::UniqueColors => count_unique_colors(context.rgb8(), mask) as f32,
+            Metric::EdgeDensity => edge_pixels_ratio(context.edges(), mask),
+            Metric::NoiseImmerkaer => immerkaer_sigma(context.gray_levels(), mask),
+            Metric::NoisePca => pca_sigma(context.gray_levels()),
+            Metric::Blockiness => blockiness(context.gray_image()),
+            Metric::Entropy => gray_entropy(context.gray_image(), mask),
+            Metric::SpatialInformation => {
+                let gradients = context.gradients();
+                spatial_information_from_gradients(&gradients.x, &gradients.y, mask).std_dev
+            }
+            Metric::SpectralSlope => spectral_features(context.gray_image()).slope,
+            Metric::PngBitsPerPixel => png_bits_per_pixel(context.rgb8()),
+            Metric::JpegBitsPerPixel => jpeg_bits_per_pixel(context.rgb8(), DEFAULT_JPEG_QUALITY),
+            Metric::FractalDimension => box_counting(&binary_from_gray(context.edges())).dimension,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Lcg;
+
+    // flat gray on the left half, noise on the right half
+    fn half_noise_image() -> Rgb32FImage {
+        let mut rng = Lcg(1);
+        Rgb32FImage::from_fn(32, 32, |x, _| {
+            let noise = rng.unit();
+            let v = if x < 16 { 0.5 } else { noise };
+            image::Rgb([v, v, v])
+        })
+    }
+
+    #[test]
+    fn masked_metrics_only_see_the_flat_half() {
+        let image = half_noise_image();
+        let mask = Mask::from_rect(32, 32, 0, 0, 16, 32);
+        let context = AnalysisContext::new(&image, Luminance::Rec709);
+
+        assert!((Metric::GrayscaleSd.compute_with(&context, Some(&mask)) - 0.5).abs() < 1e-5);
+        for metric in [Metric::Entropy, Metric::NoiseImmerkaer, Metric::SpatialInformation] {
+            assert_eq!(metric.compute_with(&context, Some(&mask)), 0.0, "{}", metric.name());
+            assert!(metric.compute_with(&context, None) > 0.0, "{}", metric.name());
+        }
+    }
+
+    #[test]
+    fn unmasked_metrics_run_on_the_bounding_box() {
+        let image = half_noise_image();
+        let mask = Mask::from_rect(32, 32, 8, 0, 16, 32);
+        let crop = image::imageops::crop_imm(&image, 8, 0, 16, 32).to_image();
+        for metric in [Metric::NoisePca, Metric::Blockiness, Metric::PngBitsPerPixel] {
+            assert!(!metric.supports_mask());
+            assert_eq!(
+                metric.compute(&image, Luminance::Rec709, Some(&mask)),
+                metric.compute(&crop, Luminance::Rec709, None),
+                "{}", metric.name()
+            );
+        }
+    }
+
+    #[test]
+    fn empty_mask_gives_zero() {
+        let image = half_noise_image();
+        let empty = Mask::from_rect(32, 32, 40, 40, 1, 1);
+        let context = AnalysisContext::new(&image, Luminance::Rec709);
+        for metric in Metric::ALL {
+            assert_eq!(metric.compute_with(&context, Some(&empty)), 0.0, "{}", metric.name());
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::f32::consts::PI;
+use image::{GrayImage, RgbImage};
+use crate::mask::{window_included, Mask};
+use crate::plane::{gray_to_plane, rgb_channel_to_plane, Plane};
+use crate::simd::convolve_3x3;
+use crate::utils::symmetric_eigenvalues;
+
+// NOISE LEVEL ESTIMATION
+// all sigmas are returned in 8-bit intensity units (0 - 255) so they can be put
+// directly next to grayscale_sd and the texture metrics
+
+// side of the square patches used by the pca estimator
+const PCA_PATCH_SIZE: usize = 5;
+// upper bound of patches fed into the covariance matrix, bigger images are sampled with a stride
+const PCA_MAX_PATCHES: usize = 40_000;
+// patches with less variance than this (a std of about 0.03 gray levels) are treated as flat,
+// which in practice are only clipped or constant areas
+const FLAT_PATCH_VARIANCE: f32 = 1e-3;
+
+pub const IMMERKAER_MASK: [[f32; 3]; 3] = [
+    [1.0, -2.0, 1.0],
+    [-2.0, 4.0, -2.0],
+    [1.0, -2.0, 1.0],
+];
+
+pub struct NoiseEstimate {
+    pub immerkaer: f32,
+    pub pca: f32,
+}
+
+// see J. Immerkær, "Fast Noise Variance Estimation", CVIU 1996
+// the mask is the difference of two laplacians so it cancels out most of the image structure,
+// what is left is (mostly) noise. Equation 2:
+// sigma = sqrt(pi / 2) * 1 / (6 * (W - 2) * (H - 2)) * sum(|I * N|)
+// with a mask the sum and the count only take the positions whose 3x3 window lies inside it
+pub fn immerkaer_sigma(plane: &Plane<f32>, mask: Option<&Mask>) -> f32 {
+    let (width, height) = (plane.width(), plane.height());
+    if height < 3 || width < 3 {
+        return 0.0;
+    }
+
+    let mut sum = 0.0f64;
+    let mut count = 0usize;
+    for (y, row) in convolve_3x3(plane, &IMMERKAER_MASK).rows().enumerate() {
+        for (x, conv) in row.iter().enumerate() {
+            if window_included(mask, x as u32, y as u32, 3) {
+                sum += conv.abs() as f64;
+                count += 1;
+            }
+        }
+    }
+    if count == 0 {
+        return 0.0;
+    }
+
+    (PI / 2.0).sqrt() * (sum / (6.0 * count as f64)) as f32
+}
+
+// patch based estimator, loosely following
+// S. Pyatykh, J. Hesser, L. Zheng, "Image Noise Level Estimation by Principal Component Analysis", IEEE TIP 2013
+// and the weak texture patch selection from
+// X. Liu, M. Tanaka, M. Okutomi, "Single-Image Noise Level Estimation for Blind Denoising", IEEE TIP 2013
+//
+// the smallest eigenvalue of the covariance of image patches is the noise variance, as long as the
+// patches do not span every direction with texture. Patches are therefore iteratively restricted to
+// the ones whose variance is explainable by noise alone at the current estimate.
+pub fn pca_sigma(plane: &Plane<f32>) -> f32 {
+    let (width, height) = (plane.width(), plane.height());
+    if height < PCA_PATCH_SIZE || width < PCA_PATCH_SIZE {
+        return 0.0;
+    }
+    let d = PCA_PATCH_SIZE * PCA_PATCH_SIZE;
+
+    let positions = (width - PCA_PATCH_SIZE + 1) * (height - PCA_PATCH_SIZE + 1);
+    let stride = ((positions as f32 / PCA_MAX_PATCHES as f32).sqrt().ceil() as usize).max(1);
+
+    let mut patches: Vec<Vec<f32>> = Vec::new();
+    for y in (0..=(height - PCA_PATCH_SIZE)).step_by(stride) {
+        for x in (0..=(width - PCA_PATCH_SIZE)).step_by(stride) {
+            let mut patch = Vec::with_capacity(d);
+            for row in plane.rows().skip(y).take(PCA_PATCH_SIZE) {
+                patch.extend_from_slice(&row[x..(x + PCA_PATCH_SIZE)]);
+            }
+            // clipped or perfectly flat areas carry no information about the noise
+            if patch_variance(&patch) > FLAT_PATCH_VARIANCE {
+                patches.push(patch);
+            }
+        }
+    }
+    if patches.len() < 2 * d {
+        return 0.0;
+    }
+
+    let variances: Vec<f32> = patches.iter().map(|p| patch_variance(p)).collect();
+
+    // 99th percentile of sigma^2 * chi^2(d - 1) / (d - 1), normal approximation
+    let tolerance = 1.0 + 2.33 * (2.0 / (d - 1) as f32).sqrt();
+
+    let all: Vec<&Vec<f32>> = patches.iter().collect();
+    let mut variance = noise_variance(&all);
+    for _ in 0..10 {
+        let threshold = variance * tolerance;
+        let next: Vec<&Vec<f32>> = patches.iter()
+            .zip(variances.iter())
+            .filter(|(_, v)| **v <= threshold)
+            .map(|(p, _)| p)
+            .collect();
+        // not enough patches to estimate a d x d covariance reliably
+        if next.len() < 2 * d {
+            break;
+        }
+        let next_variance = noise_variance(&next);
+        let converged = (next_variance - variance).abs() <= 0.001 * variance.max(1e-6);
+        variance = next_variance;
+        if converged {
+            break;
+        }
+    }
+
+    variance.max(0.0).sqrt()
+}
+
+fn patch_variance(patch: &[f32]) -> f32 {
+    let mean = patch.iter().sum::<f32>() / patch.len() as f32;
+    patch.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / patch.len() as f32
+}
+
+fn noise_variance(patches: &[&Vec<f32>]) -> f32 {
+    let d = patches[0].len();
+    let n = patches.len() as f64;
+
+    let mut mean = vec![0.0f64; d];
+    for patch in patches {
+        for (m, v) in mean.iter_mut().zip(patch.iter()) {
+            *m += *v as f64;
+        }
+    }
+    for m in mean.iter_mut() {
+        *m /= n;
+    }
+
+    let mut covariance = vec![vec![0.0f64; d]; d];
+    let mut centered = vec![0.0f64; d];
+    for patch in patches {
+        for k in 0..d {
+            centered[k] = patch[k] as f64 - mean[k];
+        }
+        for (row, ci) in covariance.iter_mut().zip(centered.iter()) {
+            for (cell, cj) in row.iter_mut().zip(centered.iter()) {
+                *cell += ci * cj;
+            }
+        }
+    }
+    for cell in covariance.iter_mut().flatten() {
+        *cell /= n - 1.0;
+    }
+
+    let smallest = symmetric_eigenvalues(&covariance)
+        .into_iter()
+        .fold(f64::INFINITY, f64::min);
+
+    // the smallest sample eigenvalue is biased low, for pure noise it sits at the lower edge of the
+    // marchenko-pastur distribution: sigma^2 * (1 - sqrt(d / n))^2
+    let bias = (1.0 - (d as f64 / n).sqrt()).powi(2);
+    (smallest / bias) as f32
+}
+
+pub fn estimate_noise(pixels: &GrayImage) -> NoiseEstimate {
+    let plane = gray_to_plane(pixels);
+    NoiseEstimate {
+        immerkaer: immerkaer_sigma(&plane, None),
+        pca: pca_sigma(&plane),
+    }
+}
+
+// one estimate per channel in r, g, b order
+pub fn estimate_noise_per_channel(image: &RgbImage) -> [NoiseEstimate; 3] {
+    [0, 1, 2].map(|channel| {
+        let plane = rgb_channel_to_plane(image, channel);
+        NoiseEstimate {
+            immerkaer: immerkaer_sigma(&plane, None),
+            pca: pca_sigma(&plane),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Lcg;
+
+    // gaussian noise around a horizontal ramp, box-muller on a fixed lcg so runs are repeatable
+    fn noisy_ramp(width: usize, height: usize, sigma: f32, seed: u64) -> Plane<f32> {
+        let mut rng = Lcg(seed);
+        // in (0, 1), the logarithm needs it above 0
+        let mut uniform = move || ((rng.next_u64() >> 40) as f32 + 0.5) / (1u64 << 24) as f32;
+        Plane::from_fn(width, height, |x, _| {
+            let (u1, u2) = (uniform(), uniform());
+            64.0 + 0.5 * x as f32 + sigma * (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+        })
+    }
+
+    #[test]
+    fn immerkaer_finds_gaussian_sigma() {
+        for sigma in [2.0, 5.0, 10.0] {
+            let estimate = immerkaer_sigma(&noisy_ramp(256, 256, sigma, 1), None);
+            assert!((estimate - sigma).abs() < 0.03 * sigma, "sigma {} estimated as {}", sigma, estimate);
+        }
+    }
+
+    #[test]
+    fn pca_finds_gaussian_sigma() {
+        for sigma in [2.0, 5.0, 10.0] {
+            let estimate = pca_sigma(&noisy_ramp(256, 256, sigma, 2));
+            assert!((estimate - sigma).abs() < 0.1 * sigma, "sigma {} estimated as {}", sigma, estimate);
+        }
+    }
+
+    #[test]
+    fn flat_image_has_no_noise() {
+        let flat = Plane::from_fn(64, 64, |_, _| 255.0);
+        assert_eq!(immerkaer_sigma(&flat, None), 0.0);
+        // every patch is skipped as clipped
+        assert_eq!(pca_sigma(&flat), 0.0);
+    }
+
+    #[test]
+    fn too_small_images_give_zero() {
+        let tiny = noisy_ramp(2, 2, 5.0, 3);
+        assert_eq!(immerkaer_sigma(&tiny, None), 0.0);
+        assert_eq!(pca_sigma(&tiny), 0.0);
+    }
+}
@@ -0,0 +1,222 @@
+use std::ops::{Index, IndexMut};
+use image::{GrayImage, Rgb32FImage, RgbImage};
+
+// PLANAR IMAGE BUFFER
+// one contiguous row major buffer per channel. Pixels are addressed as plane[(x, y)] like in
+// the image crate, rows are stride elements apart (stride >= width, the tail of a row is padding)
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plane<T> {
+    width: usize,
+    height: usize,
+    stride: usize,
+    data: Vec<T>,
+}
+
+impl<T: Copy + Default> Plane<T> {
+    // width x height plane filled with T::default()
+    pub fn new(width: usize, height: usize) -> Plane<T> {
+        Plane::with_stride(width, height, width)
+    }
+
+    // rows padded to stride elements, e.g. to keep every row aligned for simd loads
+    pub fn with_stride(width: usize, height: usize, stride: usize) -> Plane<T> {
+        assert!(stride >= width, "stride smaller than width");
+        Plane { width, height, stride, data: vec![T::default(); stride * height] }
+    }
+}
+
+impl<T: Copy> Plane<T> {
+    pub fn from_fn(width: usize, height: usize, mut f: impl FnMut(usize, usize) -> T) -> Plane<T> {
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(x, y));
+            }
+        }
+        Plane { width, height, stride: width, data }
+    }
+
+    // data has to hold exactly width * height values, row major
+    pub fn from_vec(width: usize, height: usize, data: Vec<T>) -> Plane<T> {
+        assert_eq!(data.len(), width * height, "plane data does not match its dimensions");
+        Plane { width, height, stride: width, data }
+    }
+
+    pub fn map<U: Copy>(&self, mut f: impl FnMut(T) -> U) -> Plane<U> {
+        Plane {
+            width: self.width,
+            height: self.height,
+            stride: self.width,
+            data: self.rows().flat_map(|row| row.iter().map(|v| f(*v)).collect::<Vec<U>>()).collect(),
+        }
+    }
+
+    // plane with x and y swapped
+    pub fn transpose(&self) -> Plane<T> {
+        Plane::from_fn(self.height, self.width, |x, y| self[(y, x)])
+    }
+}
+
+impl<T> Plane<T> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // elements from the start of one row to the start of the next
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn row(&self, y: usize) -> &[T] {
+        &self.data[y * self.stride..y * self.stride + self.width]
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [T] {
+        &mut self.data[y * self.stride..y * self.stride + self.width]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> + '_ {
+        (0..self.height).map(move |y| self.row(y))
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [T]> + '_ {
+        let width = self.width;
+        // the empty case would make chunks_mut panic on a zero stride
+        self.data.chunks_mut(self.stride.max(1)).map(move |row| &mut row[..width])
+    }
+
+    // every pixel in row major order, padding skipped
+    pub fn values(&self) -> impl Iterator<Item = &T> + '_ {
+        self.rows().flatten()
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        self.rows_mut().flatten()
+    }
+
+    // the whole buffer including padding
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.data
+    }
+}
+
+impl<T> Index<(usize, usize)> for Plane<T> {
+    type Output = T;
+
+    fn index(&self, (x, y): (usize, usize)) -> &T {
+        debug_assert!(x < self.width && y < self.height);
+        &self.data[y * self.stride + x]
+    }
+}
+
+impl<T> IndexMut<(usize, usize)> for Plane<T> {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut T {
+        debug_assert!(x < self.width && y < self.height);
+        &mut self.data[y * self.stride + x]
+    }
+}
+
+impl Plane<f32> {
+    pub fn sum(&self) -> f32 {
+        self.values().sum()
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.sum() / self.len() as f32
+    }
+
+    pub fn min(&self) -> f32 {
+        self.values().copied().fold(f32::INFINITY, f32::min)
+    }
+
+    pub fn max(&self) -> f32 {
+        self.values().copied().fold(f32::NEG_INFINITY, f32::max)
+    }
+}
+
+// gray levels as 0..255 floats
+pub fn gray_to_plane(pixels: &GrayImage) -> Plane<f32> {
+    Plane::from_vec(pixels.width() as usize, pixels.height() as usize, pixels.as_raw().iter().map(|v| *v as f32).collect())
+}
+
+// takes over the buffer of the image, no copy
+pub fn gray_into_plane(pixels: GrayImage) -> Plane<u8> {
+    let (width, height) = (pixels.width() as usize, pixels.height() as usize);
+    Plane::from_vec(width, height, pixels.into_raw())
+}
+
+// one channel of an 8 bit image as 0..255 floats
+pub fn rgb_channel_to_plane(image: &RgbImage, channel: usize) -> Plane<f32> {
+    Plane::from_vec(
+        image.width() as usize,
+        image.height() as usize,
+        image.pixels().map(|pix| pix[channel] as f32).collect(),
+    )
+}
+
+// the three channels of a float image, values stay in 0..1
+pub fn rgb32f_planes(image: &Rgb32FImage) -> [Plane<f32>; 3] {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    [0, 1, 2].map(|channel| Plane::from_vec(width, height, image.pixels().map(|pix| pix[channel]).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_are_addressed_x_then_y() {
+        let plane = Plane::from_fn(3, 2, |x, y| 10 * y + x);
+        assert_eq!(plane[(2, 1)], 12);
+        assert_eq!(plane.row(1), &[10, 11, 12]);
+        assert_eq!(plane, Plane::from_vec(3, 2, vec![0, 1, 2, 10, 11, 12]));
+        assert_eq!(plane.transpose()[(1, 2)], 12);
+    }
+
+    #[test]
+    fn padding_is_skipped() {
+        let mut plane: Plane<f32> = Plane::with_stride(3, 2, 8);
+        for (i, v) in plane.values_mut().enumerate() {
+            *v = i as f32;
+        }
+        assert_eq!((plane.width(), plane.height(), plane.stride()), (3, 2, 8));
+        assert_eq!(plane.as_mut_slice().len(), 16);
+        assert_eq!(plane[(0, 1)], 3.0);
+        assert_eq!(plane.values().copied().collect::<Vec<f32>>(), vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!((plane.sum(), plane.mean(), plane.min(), plane.max()), (15.0, 2.5, 0.0, 5.0));
+        // map drops the padding
+        assert_eq!(plane.map(|v| v * 2.0), Plane::from_vec(3, 2, vec![0.0, 2.0, 4.0, 6.0, 8.0, 10.0]));
+    }
+
+    #[test]
+    fn empty_planes() {
+        let mut plane: Plane<u8> = Plane::new(0, 0);
+        assert!(plane.is_empty());
+        assert_eq!(plane.rows_mut().count(), 0);
+    }
+
+    #[test]
+    fn image_conversions() {
+        let gray = GrayImage::from_fn(2, 2, |x, y| image::Luma([(x + 2 * y) as u8]));
+        assert_eq!(gray_to_plane(&gray), Plane::from_vec(2, 2, vec![0.0, 1.0, 2.0, 3.0]));
+        assert_eq!(gray_into_plane(gray), Plane::from_vec(2, 2, vec![0, 1, 2, 3]));
+
+        let rgb = RgbImage::from_pixel(1, 1, image::Rgb([1, 2, 3]));
+        assert_eq!(rgb_channel_to_plane(&rgb, 2)[(0, 0)], 3.0);
+        let planes = rgb32f_planes(&Rgb32FImage::from_pixel(1, 1, image::Rgb([0.1, 0.2, 0.3])));
+        assert_eq!(planes.map(|plane| plane[(0, 0)]), [0.1, 0.2, 0.3]);
+    }
+}
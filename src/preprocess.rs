@@ -0,0 +1,268 @@
+use image::{DynamicImage, GenericImageView, Rgba, Rgba32FImage};
+use image::imageops::FilterType;
+use crate::hdr::{input_range, tone_map, InputRange, ToneMapping};
+
+// RESOLUTION NORMALIZATION
+// most texture metrics depend on the pixel scale, so every input is brought to the same
+// canonical size before anything is measured. Float (high dynamic range) input is tone mapped
+// first, image's resize clamps float samples to [0, 1]
+
+#[derive(Clone, Copy, Debug)]
+pub enum CanonicalSize {
+    LongestSide(u32),
+    ShortestSide(u32),
+    Megapixels(f32),
+}
+
+impl CanonicalSize {
+    // longest:1024, shortest:512 or megapixels:1.5
+    pub fn parse(value: &str) -> Option<CanonicalSize> {
+        let (kind, amount) = value.split_once(':')?;
+        match kind {
+            "longest" => Some(CanonicalSize::LongestSide(amount.parse().ok()?)),
+            "shortest" => Some(CanonicalSize::ShortestSide(amount.parse().ok()?)),
+            "megapixels" => Some(CanonicalSize::Megapixels(amount.parse().ok()?)),
+            _ => None,
+        }
+    }
+
+    // the form parse() reads
+    pub fn name(&self) -> String {
+        match self {
+            CanonicalSize::LongestSide(side) => format!("longest:{}", side),
+            CanonicalSize::ShortestSide(side) => format!("shortest:{}", side),
+            CanonicalSize::Megapixels(megapixels) => format!("megapixels:{}", megapixels),
+        }
+    }
+}
+
+// what happens when the image does not have the wanted aspect ratio (width / height)
+#[derive(Clone, Copy, Debug)]
+pub enum Framing {
+    // keep the whole image and its aspect ratio
+    Full,
+    // cut the largest centered window with the aspect ratio
+    CenterCrop { aspect_ratio: f32 },
+    // pad to the aspect ratio with transparent bars, Mask::from_alpha leaves them out (edge
+    // density still sees the border of the bars)
+    Letterbox { aspect_ratio: f32 },
+}
+
+impl Framing {
+    // full, crop:1.5 or letterbox:1.0
+    pub fn parse(value: &str) -> Option<Framing> {
+        if value == "full" {
+            return Some(Framing::Full);
+        }
+        let (kind, aspect_ratio) = value.split_once(':')?;
+        let aspect_ratio: f32 = aspect_ratio.parse().ok()?;
+        if aspect_ratio <= 0.0 {
+            return None;
+        }
+        match kind {
+            "crop" => Some(Framing::CenterCrop { aspect_ratio }),
+            "letterbox" => Some(Framing::Letterbox { aspect_ratio }),
+            _ => None,
+        }
+    }
+
+    // the form parse() reads
+    pub fn name(&self) -> String {
+        match self {
+            Framing::Full => "full".to_string(),
+            Framing::CenterCrop { aspect_ratio } => format!("crop:{}", aspect_ratio),
+            Framing::Letterbox { aspect_ratio } => format!("letterbox:{}", aspect_ratio),
+        }
+    }
+}
+
+pub fn filter_from_name(name: &str) -> Option<FilterType> {
+    match name {
+        "nearest" => Some(FilterType::Nearest),
+        "triangle" => Some(FilterType::Triangle),
+        "catmullrom" => Some(FilterType::CatmullRom),
+        "gaussian" => Some(FilterType::Gaussian),
+        "lanczos3" => Some(FilterType::Lanczos3),
+        _ => None,
+    }
+}
+
+pub fn filter_name(filter: FilterType) -> &'static str {
+    match filter {
+        FilterType::Nearest => "nearest",
+        FilterType::Triangle => "triangle",
+        FilterType::CatmullRom => "catmullrom",
+        FilterType::Gaussian => "gaussian",
+        FilterType::Lanczos3 => "lanczos3",
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ResizePolicy {
+    // None keeps the resolution
+    pub size: Option<CanonicalSize>,
+    pub filter: FilterType,
+    pub framing: Framing,
+    // only applied to float images
+    pub tone_mapping: ToneMapping,
+}
+
+impl Default for ResizePolicy {
+    fn default() -> ResizePolicy {
+        ResizePolicy {
+            size: None,
+            filter: FilterType::Lanczos3,
+            framing: Framing::Full,
+            tone_mapping: ToneMapping::Reinhard,
+        }
+    }
+}
+
+pub struct Preprocessed {
+    pub image: DynamicImage,
+    // decoded size
+    pub original: (u32, u32),
+    // size the metrics see, including letterbox bars
+    pub analysis: (u32, u32),
+    // of the decoded image, before tone mapping
+    pub input: InputRange,
+}
+
+// width x height scaled to the canonical size, aspect ratio kept
+pub fn target_dimensions(width: u32, height: u32, size: CanonicalSize) -> (u32, u32) {
+    let scale = match size {
+        CanonicalSize::LongestSide(side) => side as f32 / width.max(height) as f32,
+        CanonicalSize::ShortestSide(side) => side as f32 / width.min(height) as f32,
+        CanonicalSize::Megapixels(megapixels) => (megapixels * 1e6 / (width as f32 * height as f32)).sqrt(),
+    };
+    (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    )
+}
+
+// the frame with the given aspect ratio that either fits inside (crop) or around (letterbox)
+// width x height
+fn frame_dimensions(width: u32, height: u32, aspect_ratio: f32, inside: bool) -> (u32, u32) {
+    let wider = width as f32 / height as f32 > aspect_ratio;
+    if wider == inside {
+        (((height as f32 * aspect_ratio).round() as u32).max(1), height)
+    } else {
+        (width, ((width as f32 / aspect_ratio).round() as u32).max(1))
+    }
+}
+
+fn resize(image: &DynamicImage, width: u32, height: u32, filter: FilterType) -> DynamicImage {
+    if image.dimensions() == (width, height) {
+        image.clone()
+    } else {
+        image.resize_exact(width, height, filter)
+    }
+}
+
+pub fn preprocess(image: &DynamicImage, policy: &ResizePolicy) -> Preprocessed {
+    let input = input_range(image);
+    let tone_mapped = tone_map(image, policy.tone_mapping);
+    let image = tone_mapped.as_ref().unwrap_or(image);
+    let original = image.dimensions();
+    let (width, height) = original;
+
+    let processed = match policy.framing {
+        Framing::Full => {
+            let (width, height) = policy.size.map_or(original, |size| target_dimensions(width, height, size));
+            resize(image, width, height, policy.filter)
+        }
+        Framing::CenterCrop { aspect_ratio } => {
+            let (crop_width, crop_height) = frame_dimensions(width, height, aspect_ratio, true);
+            let cropped = image.crop_imm((width - crop_width) / 2, (height - crop_height) / 2, crop_width, crop_height);
+            let (width, height) = policy.size.map_or((crop_width, crop_height), |size| {
+                target_dimensions(crop_width, crop_height, size)
+            });
+            resize(&cropped, width, height, policy.filter)
+        }
+        Framing::Letterbox { aspect_ratio } => {
+            // the canonical size applies to the padded frame, the content is scaled by the same
+            // factor and centered in it
+            let (frame_width, frame_height) = frame_dimensions(width, height, aspect_ratio, false);
+            let (target_width, target_height) = policy.size.map_or((frame_width, frame_height), |size| {
+                target_dimensions(frame_width, frame_height, size)
+            });
+            let scale = target_width as f32 / frame_width as f32;
+            let content_width = ((width as f32 * scale).round() as u32).clamp(1, target_width);
+            let content_height = ((height as f32 * scale).round() as u32).clamp(1, target_height);
+            let content = resize(image, content_width, content_height, policy.filter).to_rgba32f();
+
+            let mut framed = Rgba32FImage::from_pixel(target_width, target_height, Rgba([0.0, 0.0, 0.0, 0.0]));
+            image::imageops::replace(
+                &mut framed,
+                &content,
+                ((target_width - content_width) / 2) as i64,
+                ((target_height - content_height) / 2) as i64,
+            );
+            DynamicImage::ImageRgba32F(framed)
+        }
+    };
+
+    Preprocessed {
+        analysis: processed.dimensions(),
+        image: processed,
+        original,
+        input,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_dimensions_keep_the_aspect_ratio() {
+        assert_eq!(target_dimensions(4000, 3000, CanonicalSize::LongestSide(1024)), (1024, 768));
+        assert_eq!(target_dimensions(4000, 3000, CanonicalSize::ShortestSide(600)), (800, 600));
+        assert_eq!(target_dimensions(4000, 3000, CanonicalSize::Megapixels(3.0)), (2000, 1500));
+        // more than u32::MAX pixels
+        assert_eq!(target_dimensions(100_000, 50_000, CanonicalSize::Megapixels(2.0)), (2000, 1000));
+    }
+
+    #[test]
+    fn sizes_and_framings_parse() {
+        assert!(matches!(CanonicalSize::parse("longest:1024"), Some(CanonicalSize::LongestSide(1024))));
+        assert!(matches!(CanonicalSize::parse("megapixels:1.5"), Some(CanonicalSize::Megapixels(m)) if m == 1.5));
+        assert!(CanonicalSize::parse("longest").is_none() && CanonicalSize::parse("widest:10").is_none());
+
+        assert!(matches!(Framing::parse("full"), Some(Framing::Full)));
+        assert!(matches!(Framing::parse("crop:1.5"), Some(Framing::CenterCrop { aspect_ratio }) if aspect_ratio == 1.5));
+        assert!(Framing::parse("letterbox:0").is_none() && Framing::parse("crop:wide").is_none());
+
+        for name in ["longest:1024", "shortest:512", "megapixels:1.5"] {
+            assert_eq!(CanonicalSize::parse(name).unwrap().name(), name);
+        }
+        for name in ["full", "crop:1.5", "letterbox:1"] {
+            assert_eq!(Framing::parse(name).unwrap().name(), name);
+        }
+        for name in ["nearest", "triangle", "catmullrom", "gaussian", "lanczos3"] {
+            assert_eq!(filter_name(filter_from_name(name).unwrap()), name);
+        }
+    }
+
+    #[test]
+    fn crop_and_letterbox_frames() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(200, 100, image::Rgb([10, 20, 30])));
+
+        let policy = ResizePolicy { framing: Framing::CenterCrop { aspect_ratio: 1.0 }, ..ResizePolicy::default() };
+        let cropped = preprocess(&image, &policy);
+        assert_eq!((cropped.original, cropped.analysis), ((200, 100), (100, 100)));
+
+        // bars above and below the content are transparent
+        let policy = ResizePolicy {
+            size: Some(CanonicalSize::LongestSide(100)),
+            framing: Framing::Letterbox { aspect_ratio: 1.0 },
+            ..ResizePolicy::default()
+        };
+        let boxed = preprocess(&image, &policy);
+        assert_eq!(boxed.analysis, (100, 100));
+        let rgba = boxed.image.to_rgba8();
+        assert_eq!(rgba.get_pixel(50, 10)[3], 0);
+        assert_eq!(rgba.get_pixel(50, 50)[3], 255);
+    }
+}
@@ -0,0 +1,157 @@
+use image::{GrayImage, Luma, Rgb, Rgb32FImage};
+use image::imageops::FilterType;
+use crate::colorfulness::Luminance;
+use crate::metrics::Metric;
+use crate::plane::{rgb32f_planes, Plane};
+use crate::utils::{normalize_plane, resize_plane, separable_filter};
+
+// MULTI SCALE ANALYSIS
+// Burt, Adelson - The Laplacian Pyramid as a Compact Image Code (1983)
+
+// the 5 tap binomial approximation of a gaussian (a = 0.375) from the paper
+const REDUCE_KERNEL: [f32; 5] = [0.0625, 0.25, 0.375, 0.25, 0.0625];
+
+// metric value at one pyramid level, level 0 is the input
+pub struct ScaleValue {
+    pub level: usize,
+    pub width: u32,
+    pub height: u32,
+    pub value: f32,
+}
+
+// blur and drop every other row and column
+fn reduce(plane: &Plane<f32>) -> Plane<f32> {
+    let blurred = separable_filter(plane, &REDUCE_KERNEL);
+    Plane::from_fn(plane.width().div_ceil(2), plane.height().div_ceil(2), |x, y| blurred[(2 * x, 2 * y)])
+}
+
+// number of levels actually built, the smallest level keeps a side of at least min_side
+fn level_count(width: u32, height: u32, levels: usize, min_side: u32) -> usize {
+    let mut count = 1;
+    let mut side = width.min(height);
+    while count < levels && side.div_ceil(2) >= min_side.max(1) {
+        side = side.div_ceil(2);
+        count += 1;
+    }
+    count
+}
+
+// up to levels images, each half the size of the previous one
+pub fn gaussian_pyramid(image: &Rgb32FImage, levels: usize, min_side: u32) -> Vec<Rgb32FImage> {
+    let levels = level_count(image.width(), image.height(), levels, min_side);
+    let mut pyramid = vec![image.clone()];
+    let mut planes = rgb32f_planes(image);
+
+    for _ in 1..levels {
+        planes = [reduce(&planes[0]), reduce(&planes[1]), reduce(&planes[2])];
+        let (width, height) = (planes[0].width() as u32, planes[0].height() as u32);
+        pyramid.push(Rgb32FImage::from_fn(width, height, |x, y| {
+            let (x, y) = (x as usize, y as usize);
+            Rgb([planes[0][(x, y)], planes[1][(x, y)], planes[2][(x, y)]])
+        }));
+    }
+    pyramid
+}
+
+// band pass levels (each gaussian level minus the next one expanded to its size), the last
+// entry is the low pass residual so the levels add up to the input again
+pub fn laplacian_pyramid(plane: &Plane<f32>, levels: usize, min_side: u32) -> Vec<Plane<f32>> {
+    let levels = level_count(plane.width() as u32, plane.height() as u32, levels, min_side);
+    let mut gaussian = vec![plane.clone()];
+    for _ in 1..levels {
+        let next = reduce(gaussian.last().unwrap());
+        gaussian.push(next);
+    }
+
+    let mut pyramid = Vec::with_capacity(levels);
+    for pair in gaussian.windows(2) {
+        let (width, height) = (pair[0].width(), pair[0].height());
+        let expanded = resize_plane(&pair[1], width as u32, height as u32, FilterType::Triangle);
+        pyramid.push(Plane::from_fn(width, height, |x, y| pair[0][(x, y)] - expanded[(x, y)]));
+    }
+    pyramid.push(gaussian.pop().unwrap());
+    pyramid
+}
+
+// a laplacian level normalized to [0, 255] for viewing
+pub fn laplacian_visualization(level: &Plane<f32>) -> GrayImage {
+    let mut level = level.clone();
+    normalize_plane(&mut level);
+    GrayImage::from_fn(level.width() as u32, level.height() as u32, |x, y| {
+        Luma([(level[(x as usize, y as usize)] * 255.0).round() as u8])
+    })
+}
+
+// the metric at every level of a gaussian pyramid
+pub fn metric_per_scale(pyramid: &[Rgb32FImage], metric: Metric, luminance: Luminance) -> Vec<ScaleValue> {
+    pyramid.iter()
+        .enumerate()
+        .map(|(level, image)| ScaleValue {
+            level,
+            width: image.width(),
+            height: image.height(),
+            value: metric.compute(image, luminance, None),
+        })
+        .collect()
+}
+
+// the metric on the image scaled so its longest side is longest_side pixels, makes values of
+// differently sized images comparable. Reported as level 0
+pub fn metric_at_canonical_resolution(image: &Rgb32FImage, metric: Metric, luminance: Luminance, longest_side: u32) -> ScaleValue {
+    let scale = longest_side as f32 / image.width().max(image.height()) as f32;
+    let width = ((image.width() as f32 * scale).round() as u32).max(1);
+    let height = ((image.height() as f32 * scale).round() as u32).max(1);
+    let resized = image::imageops::resize(image, width, height, FilterType::Lanczos3);
+    ScaleValue {
+        level: 0,
+        width,
+        height,
+        value: metric.compute(&resized, luminance, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(width: usize, height: usize) -> Plane<f32> {
+        Plane::from_fn(width, height, |x, y| ((x * 7 + y * 3) % 11) as f32 / 10.0)
+    }
+
+    #[test]
+    fn levels_halve_down_to_the_minimum_side() {
+        let pyramid = laplacian_pyramid(&pattern(100, 60), 10, 8);
+        let sizes: Vec<(usize, usize)> = pyramid.iter().map(|level| (level.width(), level.height())).collect();
+        assert_eq!(sizes, vec![(100, 60), (50, 30), (25, 15), (13, 8)]);
+
+        let image = Rgb32FImage::from_pixel(100, 60, Rgb([0.2, 0.4, 0.6]));
+        assert_eq!(gaussian_pyramid(&image, 3, 8).last().unwrap().dimensions(), (25, 15));
+    }
+
+    #[test]
+    fn laplacian_levels_add_up_to_the_input() {
+        let plane = pattern(64, 48);
+        let pyramid = laplacian_pyramid(&plane, 4, 4);
+        let mut current = pyramid.last().unwrap().clone();
+        for band in pyramid.iter().rev().skip(1) {
+            let expanded = resize_plane(&current, band.width() as u32, band.height() as u32, FilterType::Triangle);
+            current = Plane::from_fn(band.width(), band.height(), |x, y| band[(x, y)] + expanded[(x, y)]);
+        }
+        assert!(current.values().zip(plane.values()).all(|(a, b)| (a - b).abs() < 1e-5));
+    }
+
+    #[test]
+    fn flat_image_has_empty_bands() {
+        let pyramid = laplacian_pyramid(&Plane::from_fn(32, 32, |_, _| 0.5), 3, 4);
+        for band in &pyramid[..2] {
+            assert!(band.values().all(|v| v.abs() < 1e-6));
+        }
+        assert!(pyramid[2].values().all(|v| (v - 0.5).abs() < 1e-6));
+
+        // the gaussian levels of a flat image stay flat, the entropy is 0 at every scale
+        let image = Rgb32FImage::from_pixel(32, 32, Rgb([0.5, 0.5, 0.5]));
+        let values = metric_per_scale(&gaussian_pyramid(&image, 3, 4), Metric::Entropy, Luminance::Rec709);
+        assert_eq!(values.iter().map(|value| value.width).collect::<Vec<u32>>(), vec![32, 16, 8]);
+        assert!(values.iter().all(|value| value.value == 0.0));
+    }
+}
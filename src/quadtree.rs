@@ -0,0 +1,173 @@
+use image::{GrayImage, Luma};
+use crate::colorfulness::LabPixel;
+use crate::plane::Plane;
+
+// QUADTREE DECOMPOSITION
+// the image is split into four quadrants until every block is homogeneous or too small to be split,
+// the more leaves the more complex the image
+
+#[derive(Clone, Copy)]
+pub enum Homogeneity {
+    // variance of the block (summed over the channels for Lab) at most this value
+    Variance(f32),
+    // max - min of the block (the largest of the per channel ranges for Lab) at most this value
+    Range(f32),
+}
+
+#[derive(Clone, Copy)]
+pub struct Block {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+}
+
+pub struct Quadtree {
+    pub leaves: Vec<Block>,
+    // number of leaves at each depth, index 0 is the whole image
+    pub depth_histogram: Vec<usize>,
+}
+
+impl Quadtree {
+    pub fn leaf_count(&self) -> usize {
+        self.leaves.len()
+    }
+}
+
+fn decompose<F>(width: u32, height: u32, min_block: u32, is_homogeneous: F) -> Quadtree
+    where F: Fn(&Block) -> bool {
+    let mut leaves = Vec::new();
+    let mut stack = vec![Block { x: 0, y: 0, width, height, depth: 0 }];
+
+    while let Some(block) = stack.pop() {
+        let too_small = block.width / 2 < min_block.max(1) || block.height / 2 < min_block.max(1);
+        if too_small || is_homogeneous(&block) {
+            leaves.push(block);
+            continue;
+        }
+
+        let left = block.width / 2;
+        let top = block.height / 2;
+        let children = [
+            (block.x, block.y, left, top),
+            (block.x + left, block.y, block.width - left, top),
+            (block.x, block.y + top, left, block.height - top),
+            (block.x + left, block.y + top, block.width - left, block.height - top),
+        ];
+        for (x, y, width, height) in children {
+            stack.push(Block { x, y, width, height, depth: block.depth + 1 });
+        }
+    }
+
+    let max_depth = leaves.iter().map(|b| b.depth).max().unwrap_or(0) as usize;
+    let mut depth_histogram = vec![0; max_depth + 1];
+    for block in leaves.iter() {
+        depth_histogram[block.depth as usize] += 1;
+    }
+
+    Quadtree { leaves, depth_histogram }
+}
+
+// (variance, range) of the values inside the block for one channel
+fn block_statistics<F: Fn(usize, usize) -> f32>(block: &Block, value: F) -> (f32, f32) {
+    let mut sum = 0.0f64;
+    let mut sum_sq = 0.0f64;
+    let mut min = f32::INFINITY;
+    let mut max = f32::NEG_INFINITY;
+    for y in block.y..(block.y + block.height) {
+        for x in block.x..(block.x + block.width) {
+            let v = value(x as usize, y as usize);
+            sum += v as f64;
+            sum_sq += (v as f64).powi(2);
+            min = min.min(v);
+            max = max.max(v);
+        }
+    }
+    let n = (block.width * block.height) as f64;
+    let variance = (sum_sq / n - (sum / n).powi(2)).max(0.0);
+    (variance as f32, max - min)
+}
+
+// decomposition of a gray plane, min_block is the smallest side a block may get
+pub fn quadtree_gray(plane: &Plane<f32>, criterion: Homogeneity, min_block: u32) -> Quadtree {
+    decompose(plane.width() as u32, plane.height() as u32, min_block, |block| {
+        let (variance, range) = block_statistics(block, |x, y| plane[(x, y)]);
+        match criterion {
+            Homogeneity::Variance(threshold) => variance <= threshold,
+            Homogeneity::Range(threshold) => range <= threshold,
+        }
+    })
+}
+
+pub fn quadtree_lab(image: &Plane<LabPixel>, criterion: Homogeneity, min_block: u32) -> Quadtree {
+    decompose(image.width() as u32, image.height() as u32, min_block, |block| {
+        let l = block_statistics(block, |x, y| image[(x, y)].l);
+        let a = block_statistics(block, |x, y| image[(x, y)].a);
+        let b = block_statistics(block, |x, y| image[(x, y)].b);
+        match criterion {
+            Homogeneity::Variance(threshold) => l.0 + a.0 + b.0 <= threshold,
+            Homogeneity::Range(threshold) => l.1.max(a.1).max(b.1) <= threshold,
+        }
+    })
+}
+
+// outlines of all leaves drawn in white on black, for looking at the partition
+pub fn quadtree_visualization(tree: &Quadtree, width: u32, height: u32) -> GrayImage {
+    let mut out = GrayImage::new(width, height);
+    for block in tree.leaves.iter() {
+        let right = block.x + block.width - 1;
+        let bottom = block.y + block.height - 1;
+        for x in block.x..=right {
+            out.put_pixel(x, block.y, Luma([255]));
+            out.put_pixel(x, bottom, Luma([255]));
+        }
+        for y in block.y..=bottom {
+            out.put_pixel(block.x, y, Luma([255]));
+            out.put_pixel(right, y, Luma([255]));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_image_is_a_single_leaf() {
+        let tree = quadtree_gray(&Plane::from_fn(64, 64, |_, _| 10.0), Homogeneity::Variance(0.0), 1);
+        assert_eq!(tree.leaf_count(), 1);
+        assert_eq!(tree.depth_histogram, vec![1]);
+    }
+
+    #[test]
+    fn single_pixel_splits_down_to_it() {
+        // every split leaves three flat quadrants and one holding the pixel, down to 1 x 1
+        let plane = Plane::from_fn(64, 64, |x, y| if (x, y) == (0, 0) { 255.0 } else { 0.0 });
+        let tree = quadtree_gray(&plane, Homogeneity::Range(0.0), 1);
+        assert_eq!(tree.leaf_count(), 19);
+        assert_eq!(tree.depth_histogram, vec![0, 3, 3, 3, 3, 3, 4]);
+
+        // blocks may not get smaller than 8 pixels
+        let tree = quadtree_gray(&plane, Homogeneity::Range(0.0), 8);
+        assert_eq!(tree.depth_histogram, vec![0, 3, 3, 4]);
+        assert!(tree.leaves.iter().all(|block| block.width == 64 >> block.depth));
+    }
+
+    #[test]
+    fn leaves_cover_the_image_once() {
+        let plane = Plane::from_fn(37, 23, |x, y| ((x * 7 + y * 13) % 5) as f32);
+        let tree = quadtree_gray(&plane, Homogeneity::Variance(0.5), 1);
+        let area: u32 = tree.leaves.iter().map(|block| block.width * block.height).sum();
+        assert_eq!(area, 37 * 23);
+    }
+
+    #[test]
+    fn lab_split_follows_any_channel() {
+        // only the a channel changes
+        let image = Plane::from_fn(16, 16, |x, _| LabPixel { l: 50.0, a: if x < 8 { -20.0 } else { 20.0 }, b: 0.0 });
+        assert_eq!(quadtree_lab(&image, Homogeneity::Range(1.0), 1).leaf_count(), 4);
+        assert_eq!(quadtree_lab(&image, Homogeneity::Range(40.0), 1).leaf_count(), 1);
+    }
+}
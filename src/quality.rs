@@ -0,0 +1,216 @@
+use image::{GrayImage, ImageBuffer, Luma};
+use image::imageops::FilterType;
+use crate::plane::{gray_to_plane, Plane};
+use crate::utils::{gamma, gaussian_kernel, separable_filter};
+
+// NO-REFERENCE QUALITY (BRISQUE)
+// see A. Mittal, A. K. Moorthy, A. C. Bovik,
+// "No-Reference Image Quality Assessment in the Spatial Domain", IEEE TIP 2012
+// https://live.ece.utexas.edu/publications/2012/TIP%20BRISQUE.pdf
+
+pub const BRISQUE_FEATURE_COUNT: usize = 36;
+
+// stabilizing constant from Equation 1, for 8-bit intensities
+const MSCN_C: f32 = 1.0;
+
+// shapes searched when fitting the generalized gaussians
+const GAMMA_MIN: f64 = 0.2;
+const GAMMA_MAX: f64 = 10.0;
+const GAMMA_STEP: f64 = 0.001;
+
+// pairwise products of neighbouring MSCN coefficients, Equations 7 - 10
+// (dy, dx) offsets for horizontal, vertical, main diagonal and secondary diagonal
+const PAIR_OFFSETS: [(i32, i32); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+// mean subtracted contrast normalized coefficients, Equations 1 - 3
+// the local mean and deviation use a 7x7 gaussian window with sigma = 7 / 6
+pub fn mscn_coefficients(plane: &Plane<f32>) -> Plane<f32> {
+    let kernel = gaussian_kernel(7.0 / 6.0, 3);
+    let mu = separable_filter(plane, &kernel);
+    let mu_sq = separable_filter(&plane.map(|v| v * v), &kernel);
+
+    Plane::from_fn(plane.width(), plane.height(), |x, y| {
+        let sigma = (mu_sq[(x, y)] - mu[(x, y)] * mu[(x, y)]).abs().sqrt();
+        (plane[(x, y)] - mu[(x, y)]) / (sigma + MSCN_C)
+    })
+}
+
+// fits a zero mean generalized gaussian by moment matching (Sharifi & Leon-Garcia)
+// returns (shape alpha, variance sigma^2)
+pub fn fit_ggd(values: &[f32]) -> (f32, f32) {
+    let n = values.len() as f64;
+    let sigma_sq = values.iter().map(|v| (*v as f64).powi(2)).sum::<f64>() / n;
+    let mean_abs = values.iter().map(|v| (*v as f64).abs()).sum::<f64>() / n;
+    if mean_abs == 0.0 {
+        return (GAMMA_MAX as f32, 0.0);
+    }
+
+    let rho = sigma_sq / mean_abs.powi(2);
+    let alpha = search_gamma(|g| gamma(1.0 / g) * gamma(3.0 / g) / gamma(2.0 / g).powi(2), rho);
+
+    (alpha as f32, sigma_sq as f32)
+}
+
+// fits an asymmetric generalized gaussian, Equations 11 - 13
+// returns (shape alpha, mean eta, left variance, right variance)
+pub fn fit_aggd(values: &[f32]) -> (f32, f32, f32, f32) {
+    let mut left_sum = 0.0f64;
+    let mut left_count = 0usize;
+    let mut right_sum = 0.0f64;
+    let mut right_count = 0usize;
+    for v in values {
+        let v = *v as f64;
+        if v < 0.0 {
+            left_sum += v * v;
+            left_count += 1;
+        } else if v > 0.0 {
+            right_sum += v * v;
+            right_count += 1;
+        }
+    }
+    if left_count == 0 || right_count == 0 {
+        return (GAMMA_MAX as f32, 0.0, 0.0, 0.0);
+    }
+
+    let left_std = (left_sum / left_count as f64).sqrt();
+    let right_std = (right_sum / right_count as f64).sqrt();
+    let gamma_hat = left_std / right_std;
+
+    let n = values.len() as f64;
+    let mean_abs = values.iter().map(|v| (*v as f64).abs()).sum::<f64>() / n;
+    let mean_sq = values.iter().map(|v| (*v as f64).powi(2)).sum::<f64>() / n;
+    let r_hat = mean_abs.powi(2) / mean_sq;
+    let r_hat_norm = r_hat * (gamma_hat.powi(3) + 1.0) * (gamma_hat + 1.0) / (gamma_hat.powi(2) + 1.0).powi(2);
+
+    let alpha = search_gamma(|g| gamma(2.0 / g).powi(2) / (gamma(1.0 / g) * gamma(3.0 / g)), r_hat_norm);
+
+    let ratio = (gamma(1.0 / alpha) / gamma(3.0 / alpha)).sqrt();
+    let beta_left = left_std * ratio;
+    let beta_right = right_std * ratio;
+    let eta = (beta_right - beta_left) * gamma(2.0 / alpha) / gamma(1.0 / alpha);
+
+    (alpha as f32, eta as f32, left_std.powi(2) as f32, right_std.powi(2) as f32)
+}
+
+// brute force search of the shape parameter whose moment ratio is closest to the target
+fn search_gamma<F: Fn(f64) -> f64>(ratio: F, target: f64) -> f64 {
+    let mut best = (f64::INFINITY, GAMMA_MIN);
+    let steps = ((GAMMA_MAX - GAMMA_MIN) / GAMMA_STEP) as usize;
+    for i in 0..=steps {
+        let g = GAMMA_MIN + i as f64 * GAMMA_STEP;
+        let error = (ratio(g) - target).abs();
+        if error < best.0 {
+            best = (error, g);
+        }
+    }
+    best.1
+}
+
+// the 18 features of a single scale: ggd fit of the MSCN coefficients followed by
+// aggd fits of the four pairwise products
+fn scale_features(plane: &Plane<f32>) -> Vec<f32> {
+    let mscn = mscn_coefficients(plane);
+    let height = mscn.height() as i32;
+    let width = mscn.width() as i32;
+
+    let flat: Vec<f32> = mscn.values().copied().collect();
+    let (alpha, sigma_sq) = fit_ggd(&flat);
+    let mut out = vec![alpha, sigma_sq];
+
+    for (dy, dx) in PAIR_OFFSETS {
+        let mut products = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let (ny, nx) = (y + dy, x + dx);
+                if ny < 0 || nx < 0 || ny >= height || nx >= width {
+                    continue;
+                }
+                products.push(mscn[(x as usize, y as usize)] * mscn[(nx as usize, ny as usize)]);
+            }
+        }
+        let (alpha, eta, left, right) = fit_aggd(&products);
+        out.extend_from_slice(&[alpha, eta, left, right]);
+    }
+
+    out
+}
+
+// the standard 36 dimensional BRISQUE feature vector: 18 features at the original
+// resolution followed by the same 18 features at half resolution
+pub fn brisque_features(pixels: &GrayImage) -> [f32; BRISQUE_FEATURE_COUNT] {
+    let plane = gray_to_plane(pixels);
+
+    // resize clamps float pixels to [0, 1] so the plane is scaled down and back up around it
+    let (width, height) = pixels.dimensions();
+    let buffer: ImageBuffer<Luma<f32>, Vec<f32>> = ImageBuffer::from_fn(width, height, |x, y| {
+        Luma([plane[(x as usize, y as usize)] / 255.0])
+    });
+    let half = image::imageops::resize(&buffer, (width / 2).max(1), (height / 2).max(1), FilterType::CatmullRom);
+    let half_plane = Plane::from_vec(
+        half.width() as usize,
+        half.height() as usize,
+        half.into_raw().iter().map(|v| v * 255.0).collect(),
+    );
+
+    let mut out = [0.0f32; BRISQUE_FEATURE_COUNT];
+    let features: Vec<f32> = scale_features(&plane).into_iter()
+        .chain(scale_features(&half_plane))
+        .collect();
+    out.copy_from_slice(&features);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Lcg;
+
+    // deterministic pseudo random values in (0, 1)
+    fn uniform_values(count: usize, seed: u64) -> Vec<f64> {
+        let mut rng = Lcg(seed);
+        (0..count).map(|_| ((rng.next_u64() >> 11) as f64 + 0.5) / (1u64 << 53) as f64).collect()
+    }
+
+    // standard normal samples, box-muller
+    fn gaussian_values(count: usize, seed: u64) -> Vec<f32> {
+        uniform_values(count * 2, seed)
+            .chunks_exact(2)
+            .map(|u| ((-2.0 * u[0].ln()).sqrt() * (2.0 * std::f64::consts::PI * u[1]).cos()) as f32)
+            .collect()
+    }
+
+    #[test]
+    fn ggd_fit_of_gaussian_samples_has_shape_two() {
+        let values: Vec<f32> = gaussian_values(50_000, 1).iter().map(|v| v * 3.0).collect();
+        let (alpha, sigma_sq) = fit_ggd(&values);
+        assert!((alpha - 2.0).abs() < 0.1, "alpha {}", alpha);
+        assert!((sigma_sq - 9.0).abs() < 0.3, "sigma^2 {}", sigma_sq);
+    }
+
+    #[test]
+    fn ggd_fit_of_laplacian_samples_has_shape_one() {
+        // inverse cdf of a laplacian with scale 1
+        let values: Vec<f32> = uniform_values(50_000, 2).iter()
+            .map(|u| -((u - 0.5).signum() * (1.0 - 2.0 * (u - 0.5).abs()).ln()) as f32)
+            .collect();
+        let (alpha, _) = fit_ggd(&values);
+        assert!((alpha - 1.0).abs() < 0.1, "alpha {}", alpha);
+    }
+
+    #[test]
+    fn aggd_fit_of_symmetric_samples() {
+        let values = gaussian_values(50_000, 3);
+        let (alpha, eta, left, right) = fit_aggd(&values);
+        assert!((alpha - 2.0).abs() < 0.15, "alpha {}", alpha);
+        assert!(eta.abs() < 0.05, "eta {}", eta);
+        assert!((left - right).abs() < 0.05, "left {} right {}", left, right);
+    }
+
+    #[test]
+    fn flat_image_has_no_mscn_spread() {
+        let features = brisque_features(&GrayImage::from_pixel(32, 32, Luma([128])));
+        // mscn variance at both scales, only float residue of the local mean is left
+        assert!(features[1] < 1e-6 && features[19] < 1e-6, "{:?}", features);
+        assert!(features.iter().all(|v| v.is_finite()));
+    }
+}
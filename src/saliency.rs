@@ -0,0 +1,229 @@
+use image::{GrayImage, Luma};
+use image::imageops::FilterType;
+use rustfft::num_complex::Complex;
+use crate::colorfulness::LabPixel;
+use crate::plane::{gray_to_plane, Plane};
+use crate::utils::{fft_2d, gaussian_kernel, normalize_plane, resize_plane, separable_filter};
+
+// SALIENCY
+// every map is a plane normalized to [0, 1], same size as the input
+
+// spectral residual works on a tiny version of the image, the paper uses 64 pixels wide
+const SR_WIDTH: u32 = 64;
+const SR_BLUR_SIGMA: f32 = 3.0;
+
+// (center, surround) gaussian sigmas of the center-surround variant
+const CENTER_SURROUND_SCALES: [(f32, f32); 3] = [(1.0, 4.0), (2.0, 8.0), (4.0, 16.0)];
+
+// salient pixels are the ones above this multiple of the mean saliency (Achanta et al.)
+pub const DEFAULT_MASK_FACTOR: f32 = 2.0;
+
+pub struct SaliencySummary {
+    // share of the image covered by the salient mask
+    pub area_ratio: f32,
+    // saliency weighted center of mass, in [0, 1] image coordinates (x, y)
+    pub centroid: (f32, f32),
+}
+
+// see X. Hou, L. Zhang, "Saliency Detection: A Spectral Residual Approach", CVPR 2007
+// the log amplitude spectrum of natural images is smooth, whatever sticks out of its local
+// average (the residual) is what makes the image unusual, transformed back it marks salient areas
+pub fn spectral_residual(pixels: &GrayImage) -> Plane<f32> {
+    let (width, height) = pixels.dimensions();
+    let small_width = SR_WIDTH.min(width);
+    let small_height = ((height as f32 * small_width as f32 / width as f32).round() as u32).max(1);
+    let small = resize_plane(&gray_to_plane(pixels), small_width, small_height, FilterType::Triangle);
+    let (w, h) = (small_width as usize, small_height as usize);
+
+    let mut spectrum: Vec<Complex<f32>> = small.values().map(|v| Complex::new(*v, 0.0)).collect();
+    fft_2d(&mut spectrum, w, h, false);
+
+    let log_amplitude = Plane::from_vec(w, h, spectrum.iter().map(|c| (c.norm() + 1e-6).ln()).collect());
+    let average = box_filter_3x3(&log_amplitude);
+
+    let mut residual: Vec<Complex<f32>> = spectrum.iter().enumerate()
+        .map(|(i, c)| {
+            let r = log_amplitude[(i % w, i / w)] - average[(i % w, i / w)];
+            Complex::from_polar(r.exp(), c.arg())
+        })
+        .collect();
+    fft_2d(&mut residual, w, h, true);
+
+    let energy = Plane::from_vec(w, h, residual.iter().map(|c| c.norm_sqr()).collect());
+    let kernel = gaussian_kernel(SR_BLUR_SIGMA, (3.0 * SR_BLUR_SIGMA) as usize);
+    let mut map = separable_filter(&energy, &kernel);
+    normalize_plane(&mut map);
+
+    let mut map = resize_plane(&map, width, height, FilterType::Triangle);
+    normalize_plane(&mut map);
+    map
+}
+
+// see R. Achanta, S. Hemami, F. Estrada, S. Süsstrunk, "Frequency-tuned Salient Region Detection", CVPR 2009
+// saliency is the distance in Lab between the image mean and a slightly blurred version of every pixel
+pub fn frequency_tuned(image: &Plane<LabPixel>) -> Plane<f32> {
+    let [l, a, b] = lab_planes(image);
+    let mean = [l.mean(), a.mean(), b.mean()];
+
+    // the 5x5 binomial kernel from the paper
+    let kernel = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+    let (l, a, b) = (separable_filter(&l, &kernel), separable_filter(&a, &kernel), separable_filter(&b, &kernel));
+
+    let mut map = Plane::from_fn(l.width(), l.height(), |x, y| {
+        ((l[(x, y)] - mean[0]).powi(2) + (a[(x, y)] - mean[1]).powi(2) + (b[(x, y)] - mean[2]).powi(2)).sqrt()
+    });
+    normalize_plane(&mut map);
+    map
+}
+
+// simple center-surround contrast: Lab distance between a fine (center) and a coarse (surround)
+// gaussian blur, summed over a few scales. Loosely after Itti, Koch, Niebur 1998
+pub fn center_surround(image: &Plane<LabPixel>) -> Plane<f32> {
+    let [l, a, b] = lab_planes(image);
+    let mut map: Plane<f32> = Plane::new(image.width(), image.height());
+
+    for (center_sigma, surround_sigma) in CENTER_SURROUND_SCALES {
+        let center_kernel = gaussian_kernel(center_sigma, (3.0 * center_sigma) as usize);
+        let surround_kernel = gaussian_kernel(surround_sigma, (3.0 * surround_sigma) as usize);
+        let center = [&l, &a, &b].map(|plane| separable_filter(plane, &center_kernel));
+        let surround = [&l, &a, &b].map(|plane| separable_filter(plane, &surround_kernel));
+
+        for (y, row) in map.rows_mut().enumerate() {
+            for (x, value) in row.iter_mut().enumerate() {
+                let distance: f32 = (0..3)
+                    .map(|c| (center[c][(x, y)] - surround[c][(x, y)]).powi(2))
+                    .sum();
+                *value += distance.sqrt();
+            }
+        }
+    }
+
+    normalize_plane(&mut map);
+    map
+}
+
+// binarized salient region, pixels above factor * mean saliency are white
+pub fn saliency_mask(map: &Plane<f32>, factor: f32) -> GrayImage {
+    let threshold = factor * map.mean();
+    GrayImage::from_fn(map.width() as u32, map.height() as u32, |x, y| {
+        if map[(x as usize, y as usize)] > threshold { Luma([255]) } else { Luma([0]) }
+    })
+}
+
+pub fn saliency_summary(map: &Plane<f32>, mask: &GrayImage) -> SaliencySummary {
+    let (width, height) = mask.dimensions();
+    let salient = mask.pixels().filter(|pix| pix[0] > 0).count() as f32;
+
+    let mut total = 0.0f64;
+    let mut sum_x = 0.0f64;
+    let mut sum_y = 0.0f64;
+    for (y, row) in map.rows().enumerate() {
+        for (x, value) in row.iter().enumerate() {
+            total += *value as f64;
+            sum_x += *value as f64 * (x as f64 + 0.5);
+            sum_y += *value as f64 * (y as f64 + 0.5);
+        }
+    }
+    let centroid = if total > 0.0 {
+        ((sum_x / total) as f32 / width as f32, (sum_y / total) as f32 / height as f32)
+    } else {
+        (0.5, 0.5)
+    };
+
+    SaliencySummary {
+        area_ratio: salient / (width * height) as f32,
+        centroid,
+    }
+}
+
+// l, a and b as separate planes
+fn lab_planes(image: &Plane<LabPixel>) -> [Plane<f32>; 3] {
+    [image.map(|p| p.l), image.map(|p| p.a), image.map(|p| p.b)]
+}
+
+// mean over the 3x3 neighbourhood, the spectrum wraps around so the borders do too
+fn box_filter_3x3(plane: &Plane<f32>) -> Plane<f32> {
+    let height = plane.height() as i32;
+    let width = plane.width() as i32;
+    Plane::from_fn(plane.width(), plane.height(), |x, y| {
+        let mut sum = 0.0;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                sum += plane[((x as i32 + dx).rem_euclid(width) as usize, (y as i32 + dy).rem_euclid(height) as usize)];
+            }
+        }
+        sum / 9.0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorfulness::rgb_to_lab;
+    use crate::utils::Lcg;
+
+    // gray 48x48 field with a red 8x8 square whose top left corner is at (8, 8)
+    fn red_square() -> Plane<LabPixel> {
+        Plane::from_fn(48, 48, |x, y| {
+            if (8..16).contains(&x) && (8..16).contains(&y) { rgb_to_lab(1.0, 0.0, 0.0) } else { rgb_to_lab(0.5, 0.5, 0.5) }
+        })
+    }
+
+    fn argmax(map: &Plane<f32>) -> (usize, usize) {
+        let mut best = (0, 0);
+        for y in 0..map.height() {
+            for x in 0..map.width() {
+                if map[(x, y)] > map[best] {
+                    best = (x, y);
+                }
+            }
+        }
+        best
+    }
+
+    fn in_square((x, y): (usize, usize), margin: usize) -> bool {
+        (8 - margin..16 + margin).contains(&x) && (8 - margin..16 + margin).contains(&y)
+    }
+
+    #[test]
+    fn frequency_tuned_finds_the_odd_patch() {
+        let map = frequency_tuned(&red_square());
+        assert!(in_square(argmax(&map), 0));
+        assert!((map.max() - 1.0).abs() < 1e-6 && map.min().abs() < 1e-6);
+
+        let mask = saliency_mask(&map, DEFAULT_MASK_FACTOR);
+        assert_eq!(mask[(11, 11)][0], 255);
+        assert_eq!(mask[(40, 40)][0], 0);
+
+        let summary = saliency_summary(&map, &mask);
+        assert!(summary.area_ratio > 0.0 && summary.area_ratio < 0.1, "{}", summary.area_ratio);
+        assert!(summary.centroid.0 < 0.5 && summary.centroid.1 < 0.5, "{:?}", summary.centroid);
+    }
+
+    #[test]
+    fn center_surround_peaks_at_the_odd_patch() {
+        let map = center_surround(&red_square());
+        assert!(in_square(argmax(&map), 2), "{:?}", argmax(&map));
+    }
+
+    #[test]
+    fn spectral_residual_peaks_at_the_odd_patch() {
+        // a flat background would give a sinc spectrum with exact zeros, it gets some texture
+        let mut rng = Lcg(5);
+        let gray = GrayImage::from_fn(48, 48, |x, y| {
+            let texture = (rng.next_u64() >> 60) as u8;
+            Luma([if (8..16).contains(&x) && (8..16).contains(&y) { 230 } else { 40 + texture }])
+        });
+        let map = spectral_residual(&gray);
+        assert_eq!((map.width(), map.height()), (48, 48));
+        assert!(in_square(argmax(&map), 4), "{:?}", argmax(&map));
+    }
+
+    #[test]
+    fn flat_map_centroid_is_the_center() {
+        let map = Plane::new(10, 10);
+        let summary = saliency_summary(&map, &saliency_mask(&map, DEFAULT_MASK_FACTOR));
+        assert_eq!(summary.centroid, (0.5, 0.5));
+        assert_eq!(summary.area_ratio, 0.0);
+    }
+}
@@ -0,0 +1,322 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use rayon::prelude::*;
+use crate::colorfulness::{rgb_to_lab, LabPixel, Luminance};
+use crate::plane::Plane;
+
+// SIMD KERNELS
+// avx2 versions of the per pixel loops that dominate the runtime, picked at runtime when the cpu
+// supports them. The scalar versions are the reference: grayscale and the convolution give the
+// same bits (same operations in the same order, no fma), lab differs by float rounding only
+// because the cube root is computed with newton steps instead of powf
+
+const LANES: usize = 8;
+
+pub fn avx2_available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx2")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+// rgb holds interleaved r, g, b values of out.len() pixels
+pub fn rgb_to_lab_row(rgb: &[f32], out: &mut [LabPixel]) {
+    assert_eq!(rgb.len(), out.len() * 3);
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        // safe, avx2 support was checked right above
+        unsafe { rgb_to_lab_row_avx2(rgb, out) };
+        return;
+    }
+    rgb_to_lab_row_scalar(rgb, out);
+}
+
+pub fn rgb_to_lab_row_scalar(rgb: &[f32], out: &mut [LabPixel]) {
+    for (pixel, lab) in rgb.chunks_exact(3).zip(out.iter_mut()) {
+        *lab = rgb_to_lab(pixel[0], pixel[1], pixel[2]);
+    }
+}
+
+// rgb holds interleaved r, g, b values of out.len() pixels
+pub fn gray_row(rgb: &[f32], out: &mut [f32], method: Luminance) {
+    assert_eq!(rgb.len(), out.len() * 3);
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() && !matches!(method, Luminance::Rec709Linear | Luminance::LabL) {
+        // safe, avx2 support was checked right above
+        unsafe { gray_row_avx2(rgb, out, method) };
+        return;
+    }
+    gray_row_scalar(rgb, out, method);
+}
+
+pub fn gray_row_scalar(rgb: &[f32], out: &mut [f32], method: Luminance) {
+    for (pixel, gray) in rgb.chunks_exact(3).zip(out.iter_mut()) {
+        *gray = method.gray(pixel[0], pixel[1], pixel[2]);
+    }
+}
+
+// 3x3 correlation (the kernel is not flipped) over the positions where the kernel fits,
+// kernel[dy][dx]. The result is (width - 2) x (height - 2), output (x, y) belongs to the window
+// with its top left corner at (x, y). Rows run in parallel
+pub fn convolve_3x3(plane: &Plane<f32>, kernel: &[[f32; 3]; 3]) -> Plane<f32> {
+    #[cfg(target_arch = "x86_64")]
+    if avx2_available() {
+        return convolve_3x3_with(plane, kernel, |rows, kernel, out| {
+            // safe, avx2 support was checked right above
+            unsafe { convolve_row_avx2(rows, kernel, out) }
+        });
+    }
+    convolve_3x3_scalar(plane, kernel)
+}
+
+pub fn convolve_3x3_scalar(plane: &Plane<f32>, kernel: &[[f32; 3]; 3]) -> Plane<f32> {
+    convolve_3x3_with(plane, kernel, convolve_row_scalar)
+}
+
+fn convolve_3x3_with<F>(plane: &Plane<f32>, kernel: &[[f32; 3]; 3], row_kernel: F) -> Plane<f32>
+    where F: Fn([&[f32]; 3], &[[f32; 3]; 3], &mut [f32]) + Sync {
+    let width = plane.width().saturating_sub(2);
+    let height = plane.height().saturating_sub(2);
+    let mut out: Plane<f32> = Plane::new(width, height);
+    if width == 0 || height == 0 {
+        return out;
+    }
+    let stride = out.stride();
+    out.as_mut_slice()
+        .par_chunks_mut(stride)
+        .enumerate()
+        .for_each(|(y, row)| row_kernel([plane.row(y), plane.row(y + 1), plane.row(y + 2)], kernel, &mut row[..width]));
+    out
+}
+
+fn convolve_row_scalar(rows: [&[f32]; 3], kernel: &[[f32; 3]; 3], out: &mut [f32]) {
+    for (x, value) in out.iter_mut().enumerate() {
+        let mut sum = 0.0f32;
+        for (row, weights) in rows.iter().zip(kernel.iter()) {
+            for (dx, weight) in weights.iter().enumerate() {
+                sum += row[x + dx] * weight;
+            }
+        }
+        *value = sum;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn convolve_row_avx2(rows: [&[f32]; 3], kernel: &[[f32; 3]; 3], out: &mut [f32]) {
+    let width = out.len();
+    let full = width - width % LANES;
+    let weights = kernel.map(|row| row.map(|w| _mm256_set1_ps(w)));
+
+    for x in (0..full).step_by(LANES) {
+        let mut sum = _mm256_setzero_ps();
+        for (row, row_weights) in rows.iter().zip(weights.iter()) {
+            for (dx, weight) in row_weights.iter().enumerate() {
+                // x + dx + 8 <= width + 2 = row length
+                let values = _mm256_loadu_ps(row.as_ptr().add(x + dx));
+                sum = _mm256_add_ps(sum, _mm256_mul_ps(values, *weight));
+            }
+        }
+        _mm256_storeu_ps(out.as_mut_ptr().add(x), sum);
+    }
+
+    let tail = [&rows[0][full..], &rows[1][full..], &rows[2][full..]];
+    convolve_row_scalar(tail, kernel, &mut out[full..]);
+}
+
+// r, g and b of 8 consecutive interleaved pixels starting at pixel
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn load_rgb(rgb: &[f32], pixel: usize) -> [__m256; 3] {
+    let offsets = _mm256_setr_epi32(0, 3, 6, 9, 12, 15, 18, 21);
+    let base = rgb.as_ptr().add(pixel * 3);
+    [
+        _mm256_i32gather_ps::<4>(base, offsets),
+        _mm256_i32gather_ps::<4>(base.add(1), offsets),
+        _mm256_i32gather_ps::<4>(base.add(2), offsets),
+    ]
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn gray_row_avx2(rgb: &[f32], out: &mut [f32], method: Luminance) {
+    let pixels = out.len();
+    let full = pixels - pixels % LANES;
+
+    // weighted sums are evaluated as (wr * r + wg * g) + wb * b like the scalar version
+    let weighted = |[r, g, b]: [__m256; 3], wr: f32, wg: f32, wb: f32| {
+        let rg = _mm256_add_ps(_mm256_mul_ps(r, _mm256_set1_ps(wr)), _mm256_mul_ps(g, _mm256_set1_ps(wg)));
+        _mm256_add_ps(rg, _mm256_mul_ps(b, _mm256_set1_ps(wb)))
+    };
+
+    for pixel in (0..full).step_by(LANES) {
+        let channels = load_rgb(rgb, pixel);
+        let gray = match method {
+            Luminance::Average => {
+                let [r, g, b] = channels;
+                _mm256_div_ps(_mm256_add_ps(_mm256_add_ps(r, g), b), _mm256_set1_ps(3.0))
+            }
+            Luminance::Rec601 => weighted(channels, 0.299, 0.587, 0.114),
+            Luminance::Rec709 => weighted(channels, 0.2126, 0.7152, 0.0722),
+            Luminance::HsvValue => {
+                let [r, g, b] = channels;
+                _mm256_max_ps(_mm256_max_ps(r, g), b)
+            }
+            Luminance::Rec709Linear | Luminance::LabL => unreachable!("no simd version"),
+        };
+        _mm256_storeu_ps(out.as_mut_ptr().add(pixel), gray);
+    }
+
+    gray_row_scalar(&rgb[full * 3..], &mut out[full..], method);
+}
+
+// cube root of non negative values. The first guess divides the exponent by 3 on the bit
+// pattern (Kahan), three newton steps y = (2y + x / y^2) / 3 bring it to float precision
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn cbrt_avx2(x: __m256) -> __m256 {
+    let bits = _mm256_cvtepi32_ps(_mm256_castps_si256(x));
+    let third = _mm256_cvttps_epi32(_mm256_mul_ps(bits, _mm256_set1_ps(1.0 / 3.0)));
+    let mut y = _mm256_castsi256_ps(_mm256_add_epi32(third, _mm256_set1_epi32(709_921_077)));
+    let two = _mm256_set1_ps(2.0);
+    let one_third = _mm256_set1_ps(1.0 / 3.0);
+    for _ in 0..3 {
+        let y_sq = _mm256_mul_ps(y, y);
+        y = _mm256_mul_ps(_mm256_add_ps(_mm256_mul_ps(two, y), _mm256_div_ps(x, y_sq)), one_third);
+    }
+    y
+}
+
+// the piecewise f(t) of the lab conversion, cube root above the threshold and linear below
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn lab_f_avx2(t: __m256) -> __m256 {
+    let above = _mm256_cmp_ps::<_CMP_GT_OQ>(t, _mm256_set1_ps(0.008856));
+    let linear = _mm256_add_ps(_mm256_mul_ps(t, _mm256_set1_ps(7.787)), _mm256_set1_ps(16.0 / 116.0));
+    _mm256_blendv_ps(linear, cbrt_avx2(t), above)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn rgb_to_lab_row_avx2(rgb: &[f32], out: &mut [LabPixel]) {
+    let pixels = out.len();
+    let full = pixels - pixels % LANES;
+
+    let row = |[r, g, b]: [__m256; 3], m: [f32; 3]| {
+        let rg = _mm256_add_ps(_mm256_mul_ps(_mm256_set1_ps(m[0]), r), _mm256_mul_ps(_mm256_set1_ps(m[1]), g));
+        _mm256_add_ps(rg, _mm256_mul_ps(_mm256_set1_ps(m[2]), b))
+    };
+
+    let (mut l, mut a, mut b) = ([0.0f32; LANES], [0.0f32; LANES], [0.0f32; LANES]);
+    for pixel in (0..full).step_by(LANES) {
+        let channels = load_rgb(rgb, pixel);
+        // same matrix and white point as rgb_to_lab
+        let x = _mm256_div_ps(row(channels, [0.412453, 0.357580, 0.180423]), _mm256_set1_ps(0.950456));
+        let y = row(channels, [0.212671, 0.715160, 0.072169]);
+        let z = _mm256_div_ps(row(channels, [0.019334, 0.119193, 0.950227]), _mm256_set1_ps(1.088754));
+
+        let (fx, fy, fz) = (lab_f_avx2(x), lab_f_avx2(y), lab_f_avx2(z));
+        _mm256_storeu_ps(l.as_mut_ptr(), _mm256_sub_ps(_mm256_mul_ps(_mm256_set1_ps(116.0), fy), _mm256_set1_ps(16.0)));
+        _mm256_storeu_ps(a.as_mut_ptr(), _mm256_mul_ps(_mm256_set1_ps(500.0), _mm256_sub_ps(fx, fy)));
+        _mm256_storeu_ps(b.as_mut_ptr(), _mm256_mul_ps(_mm256_set1_ps(200.0), _mm256_sub_ps(fy, fz)));
+
+        for (i, lab) in out[pixel..pixel + LANES].iter_mut().enumerate() {
+            *lab = LabPixel { l: l[i], a: a[i], b: b[i] };
+        }
+    }
+
+    rgb_to_lab_row_scalar(&rgb[full * 3..], &mut out[full..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Lcg;
+
+    // deterministic pseudo random values in [0, 1)
+    fn random_values(count: usize, seed: u64) -> Vec<f32> {
+        let mut rng = Lcg(seed);
+        (0..count).map(|_| rng.unit()).collect()
+    }
+
+    // random pixels plus black, white, pure primaries and values around the lab threshold,
+    // 45 pixels so the simd paths also run their scalar tail
+    fn test_pixels() -> Vec<f32> {
+        let mut rgb = vec![
+            0.0, 0.0, 0.0,
+            1.0, 1.0, 1.0,
+            1.0, 0.0, 0.0,
+            0.0, 1.0, 0.0,
+            0.0, 0.0, 1.0,
+            0.0088, 0.0088, 0.0088,
+            0.009, 0.009, 0.009,
+        ];
+        rgb.extend(random_values(38 * 3, 7));
+        rgb
+    }
+
+    #[test]
+    fn lab_matches_scalar() {
+        let rgb = test_pixels();
+        let mut simd = vec![LabPixel::default(); rgb.len() / 3];
+        let mut scalar = simd.clone();
+        rgb_to_lab_row(&rgb, &mut simd);
+        rgb_to_lab_row_scalar(&rgb, &mut scalar);
+        for (s, r) in simd.iter().zip(scalar.iter()) {
+            assert!((s.l - r.l).abs() < 1e-3, "{:?} vs {:?}", s, r);
+            assert!((s.a - r.a).abs() < 1e-3, "{:?} vs {:?}", s, r);
+            assert!((s.b - r.b).abs() < 1e-3, "{:?} vs {:?}", s, r);
+        }
+    }
+
+    #[test]
+    fn gray_matches_scalar() {
+        let rgb = test_pixels();
+        for method in Luminance::ALL {
+            let mut simd = vec![0.0f32; rgb.len() / 3];
+            let mut scalar = simd.clone();
+            gray_row(&rgb, &mut simd, method);
+            gray_row_scalar(&rgb, &mut scalar, method);
+            for (s, r) in simd.iter().zip(scalar.iter()) {
+                assert!((s - r).abs() < 1e-6, "{}: {} vs {}", method.name(), s, r);
+            }
+        }
+    }
+
+    #[test]
+    fn convolution_matches_scalar() {
+        // 37 columns leave a tail after the 8 wide chunks
+        let plane = Plane::from_vec(37, 11, random_values(37 * 11, 3).iter().map(|v| v * 255.0).collect());
+        let kernel = [[1.0, -2.0, 1.0], [-2.0, 4.0, -2.0], [1.0, -2.0, 1.0]];
+        let simd = convolve_3x3(&plane, &kernel);
+        let scalar = convolve_3x3_scalar(&plane, &kernel);
+        assert_eq!((simd.width(), simd.height()), (35, 9));
+        for (s, r) in simd.values().zip(scalar.values()) {
+            assert!((s - r).abs() < 1e-3, "{} vs {}", s, r);
+        }
+    }
+
+    #[test]
+    fn convolution_is_a_correlation() {
+        // a kernel picking the top right neighbour shifts the plane
+        let plane = Plane::from_fn(10, 6, |x, y| (x + 10 * y) as f32);
+        let kernel = [[0.0, 0.0, 1.0], [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]];
+        for out in [convolve_3x3(&plane, &kernel), convolve_3x3_scalar(&plane, &kernel)] {
+            for y in 0..out.height() {
+                for x in 0..out.width() {
+                    assert_eq!(out[(x, y)], plane[(x + 2, y)]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn convolution_of_tiny_planes_is_empty() {
+        let plane = Plane::from_fn(2, 5, |x, y| (x + y) as f32);
+        assert!(convolve_3x3(&plane, &[[1.0; 3]; 3]).is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use std::f32::consts::PI;
+use image::GrayImage;
+use image::imageops::FilterType;
+use rustfft::num_complex::Complex;
+use crate::plane::{gray_to_plane, Plane};
+use crate::utils::{fft_2d, linear_regression, resize_plane};
+
+// FREQUENCY DOMAIN FEATURES
+// all features come from the power spectrum of the (hann windowed) gray image
+
+// the fft runs on a square crop scaled to this side, keeps it fast and makes frequencies comparable
+const SPECTRUM_SIZE: u32 = 256;
+// radial frequencies (in cycles per image) used for the slope fit. The lowest ones are dominated
+// by the window, the highest by noise and aliasing
+const SLOPE_RANGE: (f32, f32) = (4.0, 64.0);
+// boundary between low and high frequencies, as a fraction of the nyquist frequency
+const HIGH_FREQUENCY_CUTOFF: f32 = 0.25;
+pub const ANGULAR_BINS: usize = 16;
+
+pub struct SpectralFeatures {
+    // alpha of the 1 / f^alpha fit of the radially averaged power spectrum, ~2 for natural images
+    pub slope: f32,
+    // r squared of that fit
+    pub slope_r_squared: f32,
+    // power above the cutoff divided by the power below it (dc excluded)
+    pub high_low_ratio: f32,
+    // share of the power per orientation, ANGULAR_BINS bins over [0, pi). The angle is the
+    // direction of the frequency vector, which is perpendicular to the edges producing it
+    pub angular_energy: Vec<f32>,
+}
+
+// power spectrum with the zero frequency moved to the center, SPECTRUM_SIZE^2
+pub fn power_spectrum(pixels: &GrayImage) -> Plane<f32> {
+    let (width, height) = pixels.dimensions();
+    let side = width.min(height);
+    let crop = image::imageops::crop_imm(pixels, (width - side) / 2, (height - side) / 2, side, side).to_image();
+    let plane = resize_plane(&gray_to_plane(&crop), SPECTRUM_SIZE, SPECTRUM_SIZE, FilterType::Triangle);
+    let n = SPECTRUM_SIZE as usize;
+
+    // mean removal and a 2d hann window, otherwise the image borders leak into every frequency
+    let mean = plane.mean();
+    let hann: Vec<f32> = (0..n).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (n - 1) as f32).cos()).collect();
+    let mut data: Vec<Complex<f32>> = Vec::with_capacity(n * n);
+    for (y, row) in plane.rows().enumerate() {
+        for (x, v) in row.iter().enumerate() {
+            data.push(Complex::new((v - mean) * hann[x] * hann[y], 0.0));
+        }
+    }
+    fft_2d(&mut data, n, n, false);
+
+    let half = n / 2;
+    Plane::from_fn(n, n, |x, y| data[((y + half) % n) * n + (x + half) % n].norm_sqr())
+}
+
+pub fn spectral_features(pixels: &GrayImage) -> SpectralFeatures {
+    let spectrum = power_spectrum(pixels);
+    let n = spectrum.width();
+    let center = (n / 2) as f32;
+    let nyquist = center;
+
+    let mut radial = vec![(0.0f64, 0usize); n / 2 + 1];
+    let mut low = 0.0f64;
+    let mut high = 0.0f64;
+    let mut angular = [0.0f64; ANGULAR_BINS];
+
+    for (y, row) in spectrum.rows().enumerate() {
+        for (x, power) in row.iter().enumerate() {
+            let fx = x as f32 - center;
+            let fy = center - y as f32;
+            let radius = (fx * fx + fy * fy).sqrt();
+            // dc and the corners beyond nyquist are not used
+            if radius < 0.5 || radius > nyquist {
+                continue;
+            }
+            let power = *power as f64;
+
+            let bin = radius.round() as usize;
+            radial[bin] = (radial[bin].0 + power, radial[bin].1 + 1);
+
+            if radius / nyquist > HIGH_FREQUENCY_CUTOFF {
+                high += power;
+            } else {
+                low += power;
+            }
+
+            let angle = fy.atan2(fx).rem_euclid(PI);
+            let angle_bin = ((angle / PI * ANGULAR_BINS as f32) as usize).min(ANGULAR_BINS - 1);
+            angular[angle_bin] += power;
+        }
+    }
+
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    for (frequency, (sum, count)) in radial.iter().enumerate() {
+        let f = frequency as f32;
+        if *count == 0 || *sum <= 0.0 || f < SLOPE_RANGE.0 || f > SLOPE_RANGE.1 {
+            continue;
+        }
+        xs.push((f as f64).ln());
+        ys.push((sum / *count as f64).ln());
+    }
+    let (slope, _, r_squared) = linear_regression(&xs, &ys);
+
+    let total: f64 = angular.iter().sum();
+    SpectralFeatures {
+        slope: -slope as f32,
+        slope_r_squared: r_squared as f32,
+        high_low_ratio: if low > 0.0 { (high / low) as f32 } else { 0.0 },
+        angular_energy: angular.iter().map(|a| if total > 0.0 { (a / total) as f32 } else { 0.0 }).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Lcg;
+
+    #[test]
+    fn stripes_peak_at_their_frequency() {
+        // 16 cycles per image along x
+        let stripes = GrayImage::from_fn(256, 256, |x, _| {
+            image::Luma([(128.0 + 100.0 * (2.0 * PI * 16.0 * x as f32 / 256.0).sin()).round() as u8])
+        });
+        let spectrum = power_spectrum(&stripes);
+        let peak = spectrum.values().cloned().fold(0.0, f32::max);
+        // a real image has a symmetric spectrum, the same peak at -16
+        assert!(spectrum[(128 + 16, 128)] == peak || spectrum[(128 - 16, 128)] == peak);
+        assert!((spectrum[(128 + 16, 128)] - spectrum[(128 - 16, 128)]).abs() < 1e-3 * peak);
+
+        // all the power is in horizontal frequencies, on both sides of the wrap around at pi
+        let features = spectral_features(&stripes);
+        let horizontal = features.angular_energy[0] + features.angular_energy[ANGULAR_BINS - 1];
+        assert!(horizontal > 0.99, "{:?}", features.angular_energy);
+        assert!(features.high_low_ratio < 0.01);
+    }
+
+    #[test]
+    fn white_noise_has_a_flat_spectrum() {
+        let mut rng = Lcg(1);
+        let noise = GrayImage::from_fn(256, 256, |_, _| image::Luma([rng.byte()]));
+        let features = spectral_features(&noise);
+        assert!(features.slope.abs() < 0.2, "slope {}", features.slope);
+        // the power is spread evenly, the ratio is the ratio of the areas, (1 - 0.25^2) / 0.25^2 = 15
+        assert!((features.high_low_ratio - 15.0).abs() < 1.5, "ratio {}", features.high_low_ratio);
+        assert!(features.angular_energy.iter().all(|share| (share - 1.0 / ANGULAR_BINS as f32).abs() < 0.02));
+    }
+
+    #[test]
+    fn flat_image_has_no_power() {
+        let features = spectral_features(&GrayImage::from_pixel(64, 64, image::Luma([50])));
+        assert_eq!(features.high_low_ratio, 0.0);
+        assert!(features.angular_energy.iter().all(|share| *share == 0.0));
+    }
+}
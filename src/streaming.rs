@@ -0,0 +1,446 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use image::error::{DecodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+use image::{ImageError, ImageFormat, Rgb32FImage};
+use tiff::decoder::{ChunkType, Decoder as TiffDecoder, DecodingResult};
+use crate::colorfulness::{chroma, grayscale, lab_saturation, rgb_to_lab_image, Luminance};
+use crate::image_process::{entropy_of_histogram, SpatialInformation};
+use crate::metrics::{Metric, CANNY_HIGH};
+use crate::noise::IMMERKAER_MASK;
+use crate::plane::Plane;
+use crate::simd::convolve_3x3;
+use crate::utils::{to_8bit, SOBEL_X, SOBEL_Y};
+
+// STREAMING ANALYSIS
+// images too large for a whole Rgb32FImage (gigapixel scans, satellite mosaics) are read a strip
+// of rows at a time and folded into running sums, only one strip is ever decoded at once.
+// The 3x3 kernels need the rows around every pixel, so each strip is prefixed with the last
+// KERNEL_OVERLAP rows of the previous one. Every pixel is then the center of exactly one kernel
+// window and the results match a run on the whole image
+
+pub const DEFAULT_STRIP_ROWS: u32 = 256;
+
+// rows shared with the previous strip, kernel size - 1
+const KERNEL_OVERLAP: usize = 2;
+
+// one bit per 24 bit color
+const COLOR_WORDS: usize = (1 << 24) / 64;
+
+enum Source {
+    Png(Box<png::Reader<BufReader<File>>>),
+    Tiff(Box<TiffDecoder<BufReader<File>>>),
+}
+
+// reads an image as consecutive strips of rows, png row by row and tiff strip by strip (or one
+// row of tiles at a time). Alpha is dropped like in to_rgb32f
+pub struct StripReader {
+    source: Source,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    channels: usize,
+    // decoded rgb values not handed out yet, always whole rows
+    pending: Vec<f32>,
+    decoded_rows: u32,
+}
+
+impl StripReader {
+    pub fn open(path: &Path) -> Result<StripReader, ImageError> {
+        let format = ImageFormat::from_path(path)?;
+        let file = BufReader::new(File::open(path)?);
+        match format {
+            ImageFormat::Png => {
+                let mut decoder = png::Decoder::new(file);
+                // palette and low bit depths to 8 bit, 16 bit stays
+                decoder.set_transformations(png::Transformations::EXPAND);
+                let reader = decoder.read_info().map_err(|e| decoding_error(format, e))?;
+                if reader.info().interlaced {
+                    return Err(unsupported(format, "interlaced png can not be read in strips"));
+                }
+                let (width, height) = reader.info().size();
+                let channels = match reader.output_color_type().0 {
+                    png::ColorType::Grayscale => 1,
+                    png::ColorType::GrayscaleAlpha => 2,
+                    png::ColorType::Rgb => 3,
+                    png::ColorType::Rgba => 4,
+                    png::ColorType::Indexed => return Err(unsupported(format, "unexpanded palette")),
+                };
+                Ok(StripReader::new(Source::Png(Box::new(reader)), format, width, height, channels))
+            }
+            ImageFormat::Tiff => {
+                let mut decoder = TiffDecoder::new(file).map_err(|e| decoding_error(format, e))?;
+                let (width, height) = decoder.dimensions().map_err(|e| decoding_error(format, e))?;
+                let channels = match decoder.colortype().map_err(|e| decoding_error(format, e))? {
+                    tiff::ColorType::Gray(8 | 16 | 32 | 64) => 1,
+                    tiff::ColorType::GrayA(8 | 16 | 32 | 64) => 2,
+                    tiff::ColorType::RGB(8 | 16 | 32 | 64) => 3,
+                    tiff::ColorType::RGBA(8 | 16 | 32 | 64) => 4,
+                    color => return Err(unsupported(format, &format!("tiff color type {:?}", color))),
+                };
+                Ok(StripReader::new(Source::Tiff(Box::new(decoder)), format, width, height, channels))
+            }
+            _ => Err(unsupported(format, "only png and tiff can be streamed")),
+        }
+    }
+
+    fn new(source: Source, format: ImageFormat, width: u32, height: u32, channels: usize) -> StripReader {
+        StripReader { source, format, width, height, channels, pending: Vec::new(), decoded_rows: 0 }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    // the next (up to) rows rows, None once the whole image was read
+    pub fn next_strip(&mut self, rows: u32) -> Result<Option<Rgb32FImage>, ImageError> {
+        let row_len = self.width as usize * 3;
+        let wanted = rows.max(1) as usize * row_len;
+        while self.pending.len() < wanted && self.decoded_rows < self.height {
+            self.decode_rows()?;
+        }
+        if self.pending.is_empty() || row_len == 0 {
+            return Ok(None);
+        }
+
+        let rest = self.pending.split_off(wanted.min(self.pending.len()));
+        let strip = std::mem::replace(&mut self.pending, rest);
+        let strip_rows = (strip.len() / row_len) as u32;
+        Ok(Rgb32FImage::from_raw(self.width, strip_rows, strip))
+    }
+
+    // appends the rows of the next png row, tiff strip or row of tiles to pending
+    fn decode_rows(&mut self) -> Result<(), ImageError> {
+        let format = self.format;
+        match &mut self.source {
+            Source::Png(reader) => {
+                let (_, depth) = reader.output_color_type();
+                let row = reader.next_row().map_err(|e| decoding_error(format, e))?;
+                let Some(row) = row else {
+                    return Err(decoding_error(format, "image ended early"));
+                };
+                let samples: Vec<f32> = match depth {
+                    png::BitDepth::Sixteen => row.data()
+                        .chunks_exact(2)
+                        .map(|v| u16::from_be_bytes([v[0], v[1]]) as f32 / u16::MAX as f32)
+                        .collect(),
+                    _ => row.data().iter().map(|v| *v as f32 / u8::MAX as f32).collect(),
+                };
+                push_rgb(&samples, self.channels, &mut self.pending);
+                self.decoded_rows += 1;
+            }
+            Source::Tiff(decoder) => {
+                let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+                let chunk_row = self.decoded_rows / chunk_height;
+                match decoder.get_chunk_type() {
+                    ChunkType::Strip => {
+                        let samples = read_tiff_chunk(decoder, chunk_row, format)?;
+                        let before = self.pending.len();
+                        push_rgb(&samples, self.channels, &mut self.pending);
+                        self.decoded_rows += ((self.pending.len() - before) / (self.width as usize * 3)) as u32;
+                    }
+                    ChunkType::Tile => {
+                        // the tiles of one row are decoded one after the other into full rows
+                        let tiles_across = self.width.div_ceil(chunk_width);
+                        let row_len = self.width as usize * 3;
+                        let mut rows = Vec::new();
+                        let mut rows_in_chunk = 0;
+                        for column in 0..tiles_across {
+                            let index = chunk_row * tiles_across + column;
+                            let (data_width, data_height) = decoder.chunk_data_dimensions(index);
+                            let samples = read_tiff_chunk(decoder, index, format)?;
+                            let mut tile = Vec::new();
+                            push_rgb(&samples, self.channels, &mut tile);
+
+                            if rows.is_empty() {
+                                rows_in_chunk = data_height as usize;
+                                rows = vec![0.0; rows_in_chunk * row_len];
+                            }
+                            let x = (column * chunk_width) as usize * 3;
+                            let tile_row_len = data_width as usize * 3;
+                            for (y, tile_row) in tile.chunks_exact(tile_row_len).take(rows_in_chunk).enumerate() {
+                                rows[y * row_len + x..y * row_len + x + tile_row_len].copy_from_slice(tile_row);
+                            }
+                        }
+                        self.pending.extend_from_slice(&rows);
+                        self.decoded_rows += rows_in_chunk as u32;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// samples of one chunk scaled to [0, 1], floats are taken as they are
+fn read_tiff_chunk(decoder: &mut TiffDecoder<BufReader<File>>, index: u32, format: ImageFormat) -> Result<Vec<f32>, ImageError> {
+    match decoder.read_chunk(index).map_err(|e| decoding_error(format, e))? {
+        DecodingResult::U8(data) => Ok(data.iter().map(|v| *v as f32 / u8::MAX as f32).collect()),
+        DecodingResult::U16(data) => Ok(data.iter().map(|v| *v as f32 / u16::MAX as f32).collect()),
+        DecodingResult::U32(data) => Ok(data.iter().map(|v| (*v as f64 / u32::MAX as f64) as f32).collect()),
+        DecodingResult::F32(data) => Ok(data),
+        DecodingResult::F64(data) => Ok(data.iter().map(|v| *v as f32).collect()),
+        _ => Err(unsupported(format, "signed tiff samples")),
+    }
+}
+
+// gray is repeated over the three channels, alpha is dropped
+fn push_rgb(samples: &[f32], channels: usize, out: &mut Vec<f32>) {
+    for pixel in samples.chunks_exact(channels) {
+        match channels {
+            1 | 2 => out.extend_from_slice(&[pixel[0]; 3]),
+            _ => out.extend_from_slice(&pixel[..3]),
+        }
+    }
+}
+
+fn decoding_error(format: ImageFormat, error: impl Into<Box<dyn Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(format), error))
+}
+
+fn unsupported(format: ImageFormat, feature: &str) -> ImageError {
+    ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        ImageFormatHint::Exact(format),
+        UnsupportedErrorKind::GenericFeature(feature.to_string()),
+    ))
+}
+
+// running sums of everything the streamable metrics need, strips are added top to bottom
+pub struct StreamingAccumulator {
+    luminance: Luminance,
+    width: usize,
+    height: usize,
+    pixels: u64,
+    // cieLAB moments for the colorfulness metrics
+    sum_a: f64,
+    sum_a_sq: f64,
+    sum_b: f64,
+    sum_b_sq: f64,
+    sum_chroma: f64,
+    sum_saturation: f64,
+    sum_saturation_sq: f64,
+    sum_gray_sq: f64,
+    // 8 bit gray levels
+    histogram: [u64; 256],
+    colors: Vec<u64>,
+    // last rows of the previous strip as 0..255 gray levels, see KERNEL_OVERLAP
+    carry: Vec<f32>,
+    // pixels the 3x3 kernels were centered on
+    kernel_pixels: u64,
+    sum_sobel: f64,
+    sum_sobel_sq: f64,
+    edge_pixels: u64,
+    sum_immerkaer: f64,
+}
+
+pub struct StreamingReport {
+    pub width: u32,
+    pub height: u32,
+    pub colorfulness_1: f32,
+    pub colorfulness_2: f32,
+    pub colorfulness_3: f32,
+    pub grayscale_sd: f32,
+    pub unique_colors: usize,
+    pub entropy: f32,
+    pub histogram: [u64; 256],
+    // share of pixels whose sobel magnitude is above CANNY_HIGH. Canny's non maximum suppression
+    // and hysteresis need the whole image, so this is not the same value as Metric::EdgeDensity
+    pub edge_density: f32,
+    pub spatial_information: SpatialInformation,
+    pub noise_immerkaer: f32,
+}
+
+impl StreamingAccumulator {
+    pub fn new(width: u32, height: u32, luminance: Luminance) -> StreamingAccumulator {
+        StreamingAccumulator {
+            luminance,
+            width: width as usize,
+            height: height as usize,
+            pixels: 0,
+            sum_a: 0.0,
+            sum_a_sq: 0.0,
+            sum_b: 0.0,
+            sum_b_sq: 0.0,
+            sum_chroma: 0.0,
+            sum_saturation: 0.0,
+            sum_saturation_sq: 0.0,
+            sum_gray_sq: 0.0,
+            histogram: [0; 256],
+            colors: vec![0; COLOR_WORDS],
+            carry: Vec::new(),
+            kernel_pixels: 0,
+            sum_sobel: 0.0,
+            sum_sobel_sq: 0.0,
+            edge_pixels: 0,
+            sum_immerkaer: 0.0,
+        }
+    }
+
+    pub fn add_strip(&mut self, strip: &Rgb32FImage) {
+        assert_eq!(strip.width() as usize, self.width, "strip width does not match the image");
+        self.pixels += strip.width() as u64 * strip.height() as u64;
+
+        for lab in rgb_to_lab_image(strip).values() {
+            let saturation = lab_saturation(lab) as f64;
+            self.sum_a += lab.a as f64;
+            self.sum_a_sq += (lab.a as f64).powi(2);
+            self.sum_b += lab.b as f64;
+            self.sum_b_sq += (lab.b as f64).powi(2);
+            self.sum_chroma += chroma(lab) as f64;
+            self.sum_saturation += saturation;
+            self.sum_saturation_sq += saturation * saturation;
+        }
+
+        for rgb in strip.as_raw().chunks_exact(3) {
+            let color = (to_8bit(rgb[0]) as usize) << 16 | (to_8bit(rgb[1]) as usize) << 8 | to_8bit(rgb[2]) as usize;
+            self.colors[color / 64] |= 1 << (color % 64);
+        }
+
+        let gray = grayscale(strip, self.luminance);
+        let mut window = std::mem::take(&mut self.carry);
+        for v in gray.values() {
+            let level = to_8bit(*v);
+            self.sum_gray_sq += (*v as f64).powi(2);
+            self.histogram[level as usize] += 1;
+            window.push(level as f32);
+        }
+        self.add_kernel_window(window);
+    }
+
+    // convolves the carried rows plus the new strip, then keeps the last rows for the next strip
+    fn add_kernel_window(&mut self, window: Vec<f32>) {
+        let rows = window.len() / self.width.max(1);
+        let window = Plane::from_vec(self.width, rows, window);
+
+        let gradients_x = convolve_3x3(&window, &SOBEL_X);
+        let gradients_y = convolve_3x3(&window, &SOBEL_Y);
+        for (gx, gy) in gradients_x.values().zip(gradients_y.values()) {
+            let magnitude = (gx * gx + gy * gy).sqrt();
+            self.sum_sobel += magnitude as f64;
+            self.sum_sobel_sq += (magnitude as f64).powi(2);
+            if magnitude > CANNY_HIGH {
+                self.edge_pixels += 1;
+            }
+        }
+        self.kernel_pixels += gradients_x.len() as u64;
+        self.sum_immerkaer += convolve_3x3(&window, &IMMERKAER_MASK).values().map(|v| v.abs() as f64).sum::<f64>();
+
+        let keep = rows.min(KERNEL_OVERLAP);
+        self.carry = window.rows().skip(rows - keep).flatten().copied().collect();
+    }
+
+    pub fn finish(&self) -> StreamingReport {
+        let n = self.pixels.max(1) as f64;
+        let std_dev_of_a = (self.sum_a_sq / n - (self.sum_a / n).powi(2)).max(0.0).sqrt() as f32;
+        let std_dev_of_b = (self.sum_b_sq / n - (self.sum_b / n).powi(2)).max(0.0).sqrt() as f32;
+        let mean_of_chroma = (self.sum_chroma / n) as f32;
+        let mean_of_saturation = self.sum_saturation / n;
+        let std_dev_of_saturation = (self.sum_saturation_sq / n - mean_of_saturation.powi(2)).max(0.0).sqrt();
+
+        // same formulas as colorfulness_metrics_1_3 and colorfulness_metrics_2
+        let (larger, smaller) = (std_dev_of_a.max(std_dev_of_b), std_dev_of_a.min(std_dev_of_b));
+        let colorfulness_1 = larger + 1.46 * smaller + 1.34 * mean_of_chroma;
+        let colorfulness_3 = (std_dev_of_a.powi(2) + std_dev_of_b.powi(2)).sqrt() + 0.94 * mean_of_chroma;
+        let colorfulness_2 = (mean_of_saturation + std_dev_of_saturation) as f32;
+
+        let kernel_pixels = self.kernel_pixels.max(1) as f64;
+        let sobel_mean = self.sum_sobel / kernel_pixels;
+        let empty = self.pixels == 0;
+
+        StreamingReport {
+            width: self.width as u32,
+            height: self.height as u32,
+            colorfulness_1: if empty { 0.0 } else { colorfulness_1 },
+            colorfulness_2: if empty { 0.0 } else { colorfulness_2 },
+            colorfulness_3: if empty { 0.0 } else { colorfulness_3 },
+            grayscale_sd: (self.sum_gray_sq / n).sqrt() as f32,
+            unique_colors: self.colors.iter().map(|word| word.count_ones() as usize).sum(),
+            entropy: entropy_of_histogram(&self.histogram),
+            histogram: self.histogram,
+            edge_density: (self.edge_pixels as f64 / kernel_pixels) as f32,
+            spatial_information: SpatialInformation {
+                std_dev: (self.sum_sobel_sq / kernel_pixels - sobel_mean * sobel_mean).max(0.0).sqrt() as f32,
+                mean: sobel_mean as f32,
+                rms: (self.sum_sobel_sq / kernel_pixels).sqrt() as f32,
+            },
+            // see immerkaer_sigma
+            noise_immerkaer: ((std::f64::consts::PI / 2.0).sqrt() * self.sum_immerkaer / (6.0 * kernel_pixels)) as f32,
+        }
+    }
+}
+
+impl StreamingReport {
+    // the value Metric::compute would give on the whole image, None for the metrics that can
+    // not be computed from strips
+    pub fn value(&self, metric: Metric) -> Option<f32> {
+        match metric {
+            Metric::Colorfulness1 => Some(self.colorfulness_1),
+            Metric::Colorfulness2 => Some(self.colorfulness_2),
+            Metric::Colorfulness3 => Some(self.colorfulness_3),
+            Metric::GrayscaleSd => Some(self.grayscale_sd),
+            Metric::UniqueColors => Some(self.unique_colors as f32),
+            Metric::Entropy => Some(self.entropy),
+            Metric::SpatialInformation => Some(self.spatial_information.std_dev),
+            Metric::NoiseImmerkaer => Some(self.noise_immerkaer),
+            _ => None,
+        }
+    }
+}
+
+// reads the file strip_rows rows at a time, memory use depends on the width and strip_rows only
+pub fn analyze_streaming(path: &Path, luminance: Luminance, strip_rows: u32) -> Result<StreamingReport, ImageError> {
+    let mut reader = StripReader::open(path)?;
+    let (width, height) = reader.dimensions();
+    let mut accumulator = StreamingAccumulator::new(width, height, luminance);
+    while let Some(strip) = reader.next_strip(strip_rows)? {
+        accumulator.add_strip(&strip);
+    }
+    Ok(accumulator.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Lcg;
+
+    fn textured_image(width: u32, height: u32) -> image::RgbImage {
+        let mut rng = Lcg(1);
+        image::RgbImage::from_fn(width, height, |x, y| {
+            let noise = (rng.next_u64() >> 59) as u8;
+            image::Rgb([(x * 3) as u8 + noise, (y * 5) as u8, ((x + y) * 2) as u8 + noise])
+        })
+    }
+
+    #[test]
+    fn strips_give_the_whole_image_values() {
+        let image = textured_image(61, 47);
+        let path = std::env::temp_dir().join(format!("streaming_{}.png", std::process::id()));
+        image.save(&path).unwrap();
+        // 47 rows in strips of 10, the last one is 7 rows
+        let report = analyze_streaming(&path, Luminance::Rec709, 10).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let whole = image::DynamicImage::ImageRgb8(image).to_rgb32f();
+        assert_eq!((report.width, report.height), (61, 47));
+        for metric in Metric::ALL {
+            if let Some(value) = report.value(metric) {
+                let expected = metric.compute(&whole, Luminance::Rec709, None);
+                assert!((value - expected).abs() <= 1e-3 * expected.abs().max(1.0), "{}: {} != {}", metric.name(), value, expected);
+            }
+        }
+        assert_eq!(report.histogram.iter().sum::<u64>(), 61 * 47);
+    }
+
+    #[test]
+    fn single_row_strips_carry_the_kernel_rows() {
+        let whole = image::DynamicImage::ImageRgb8(textured_image(20, 9)).to_rgb32f();
+        let mut accumulator = StreamingAccumulator::new(20, 9, Luminance::Rec709);
+        for y in 0..9 {
+            accumulator.add_strip(&image::imageops::crop_imm(&whole, 0, y, 20, 1).to_image());
+        }
+        let report = accumulator.finish();
+        let expected = Metric::SpatialInformation.compute(&whole, Luminance::Rec709, None);
+        assert!((report.spatial_information.std_dev - expected).abs() < 1e-3 * expected);
+    }
+}
@@ -0,0 +1,171 @@
+use image::{GrayImage, Luma, Rgb32FImage};
+use image::imageops::FilterType;
+use crate::colorfulness::Luminance;
+use crate::mask::Mask;
+use crate::metrics::Metric;
+use crate::plane::Plane;
+use crate::utils::{normalize_plane, resize_plane};
+
+// PER TILE FEATURE MAPS
+
+#[derive(Clone, Copy)]
+pub enum Tiling {
+    // columns x rows tiles covering the image, the last column / row takes the remainder
+    Grid { columns: u32, rows: u32 },
+    // size x size windows moved by stride pixels, windows that would stick out are dropped
+    Sliding { size: u32, stride: u32 },
+}
+
+#[derive(Clone, Copy)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct FeatureMap {
+    pub metric: Metric,
+    pub columns: usize,
+    pub rows: usize,
+    // metric value per tile, columns x rows
+    pub values: Plane<f32>,
+    pub tiles: Vec<Vec<Tile>>,
+}
+
+impl FeatureMap {
+    pub fn mean(&self) -> f32 {
+        self.values.mean()
+    }
+
+    pub fn variance(&self) -> f32 {
+        let mean = self.mean();
+        self.values.values().map(|v| (v - mean).powi(2)).sum::<f32>() / (self.columns * self.rows) as f32
+    }
+
+    pub fn max(&self) -> f32 {
+        self.values.max()
+    }
+
+    // the map scaled up to the image size and normalized to [0, 255], nearest neighbour so
+    // the tiles stay visible
+    pub fn heatmap(&self, width: u32, height: u32) -> GrayImage {
+        let mut values = self.values.clone();
+        normalize_plane(&mut values);
+        let scaled = resize_plane(&values, width, height, FilterType::Nearest);
+        GrayImage::from_fn(width, height, |x, y| {
+            Luma([(scaled[(x as usize, y as usize)].clamp(0.0, 1.0) * 255.0).round() as u8])
+        })
+    }
+}
+
+// tiles laid out in rows, outer vec is the tile row. An empty image has no tiles
+pub fn tiles(width: u32, height: u32, tiling: Tiling) -> Vec<Vec<Tile>> {
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    match tiling {
+        Tiling::Grid { columns, rows } => {
+            let (columns, rows) = (columns.clamp(1, width), rows.clamp(1, height));
+            let (tile_width, tile_height) = (width / columns, height / rows);
+            (0..rows)
+                .map(|row| {
+                    (0..columns)
+                        .map(|column| Tile {
+                            x: column * tile_width,
+                            y: row * tile_height,
+                            width: if column == columns - 1 { width - column * tile_width } else { tile_width },
+                            height: if row == rows - 1 { height - row * tile_height } else { tile_height },
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+        Tiling::Sliding { size, stride } => {
+            let size = size.min(width).min(height);
+            let stride = stride.max(1);
+            (0..=(height - size))
+                .step_by(stride as usize)
+                .map(|y| {
+                    (0..=(width - size))
+                        .step_by(stride as usize)
+                        .map(|x| Tile { x, y, width: size, height: size })
+                        .collect()
+                })
+                .collect()
+        }
+    }
+}
+
+// with a mask every tile only looks at its part of the mask
+pub fn feature_map(
+    image: &Rgb32FImage,
+    tiling: Tiling,
+    metric: Metric,
+    luminance: Luminance,
+    mask: Option<&Mask>,
+) -> FeatureMap {
+    let tiles = tiles(image.width(), image.height(), tiling);
+    let (columns, rows) = (tiles.first().map_or(0, Vec::len), tiles.len());
+    let values = Plane::from_fn(columns, rows, |column, row| {
+        let tile = tiles[row][column];
+        let view = image::imageops::crop_imm(image, tile.x, tile.y, tile.width, tile.height).to_image();
+        let tile_mask = mask.map(|mask| mask.crop(tile.x, tile.y, tile.width, tile.height));
+        metric.compute(&view, luminance, tile_mask.as_ref())
+    });
+
+    FeatureMap {
+        metric,
+        columns,
+        rows,
+        values,
+        tiles,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_images_have_no_tiles() {
+        assert!(tiles(0, 7, Tiling::Grid { columns: 3, rows: 2 }).is_empty());
+        assert!(tiles(10, 0, Tiling::Sliding { size: 4, stride: 2 }).is_empty());
+        let map = feature_map(&Rgb32FImage::new(0, 0), Tiling::Grid { columns: 3, rows: 2 }, Metric::Entropy, Luminance::Rec709, None);
+        assert_eq!((map.columns, map.rows), (0, 0));
+    }
+
+    #[test]
+    fn grid_gives_the_remainder_to_the_last_tiles() {
+        let grid = tiles(10, 7, Tiling::Grid { columns: 3, rows: 2 });
+        assert_eq!((grid[0].len(), grid.len()), (3, 2));
+        let widths: Vec<u32> = grid[0].iter().map(|tile| tile.width).collect();
+        assert_eq!(widths, vec![3, 3, 4]);
+        assert_eq!((grid[1][2].x, grid[1][2].y, grid[1][2].height), (6, 3, 4));
+    }
+
+    #[test]
+    fn sliding_windows_stay_inside() {
+        let windows = tiles(10, 7, Tiling::Sliding { size: 4, stride: 3 });
+        // x in 0, 3, 6 and y in 0, 3
+        assert_eq!((windows[0].len(), windows.len()), (3, 2));
+        assert!(windows.iter().flatten().all(|tile| tile.x + tile.width <= 10 && tile.y + tile.height <= 7));
+    }
+
+    #[test]
+    fn feature_map_of_a_half_textured_image() {
+        // flat left half, checkerboard right half
+        let image = Rgb32FImage::from_fn(16, 8, |x, y| {
+            let v = if x < 8 { 0.5 } else { ((x + y) % 2) as f32 };
+            image::Rgb([v, v, v])
+        });
+        let map = feature_map(&image, Tiling::Grid { columns: 2, rows: 1 }, Metric::Entropy, Luminance::Rec709, None);
+        // one gray level on the left, two equally frequent ones on the right
+        assert_eq!(map.values[(0, 0)], 0.0);
+        assert!((map.values[(1, 0)] - 1.0).abs() < 1e-6, "{}", map.values[(1, 0)]);
+        assert_eq!(map.max(), map.values[(1, 0)]);
+
+        let heatmap = map.heatmap(16, 8);
+        assert_eq!((heatmap.get_pixel(0, 0)[0], heatmap.get_pixel(15, 7)[0]), (0, 255));
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs;
+use crate::colorfulness::Luminance;
+use crate::plane::Plane;
+use crate::utils::{mean_plane, std_dev_plane};
+
+// EXPOSURE AND TONAL DISTRIBUTION
+// everything here works on a gray plane in [0, 1] as returned by grayscale(), the dynamic range
+// needs the luminance method the plane was made with to get back to linear light
+
+// pixels at or beyond these levels are counted as clipped
+const HIGHLIGHT_CLIP: f32 = 254.0 / 255.0;
+const SHADOW_CLIP: f32 = 1.0 / 255.0;
+
+// percentiles used for the dynamic range, the extreme tails are mostly noise and clipping
+const DYNAMIC_RANGE_PERCENTILES: (f32, f32) = (0.5, 99.5);
+// darkest linear value considered, keeps log2 finite for pure black
+const MIN_LINEAR: f32 = 1.0 / 4096.0;
+
+pub const REPORTED_PERCENTILES: [f32; 7] = [1.0, 5.0, 25.0, 50.0, 75.0, 95.0, 99.0];
+
+pub struct TonalFeatures {
+    pub mean: f32,
+    // (percentile, value) pairs for REPORTED_PERCENTILES
+    pub percentiles: Vec<(f32, f32)>,
+    pub highlight_clipping: f32,
+    pub shadow_clipping: f32,
+    pub dynamic_range_stops: f32,
+    pub skewness: f32,
+    pub kurtosis: f32,
+    pub rms_contrast: f32,
+    pub std_dev: f32,
+}
+
+// percentile (0 - 100) of already sorted values with linear interpolation between ranks
+pub fn percentile(sorted: &[f32], p: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0).clamp(0.0, 1.0) * (sorted.len() - 1) as f32;
+    let low = rank.floor() as usize;
+    let high = rank.ceil() as usize;
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f32)
+}
+
+// root mean square of the intensities, sqrt(mean(I^2)).
+// this is what grayscale_sd has always returned, kept under its real name
+pub fn rms_contrast(plane: &Plane<f32>) -> f32 {
+    std_dev_plane(plane)
+}
+
+// standard deviation of the intensities around their mean
+pub fn std_dev(plane: &Plane<f32>) -> f32 {
+    let mean = mean_plane(plane);
+    let count = plane.len() as f32;
+    let sum: f32 = plane.values().map(|v| (v - mean).powi(2)).sum();
+    (sum / count).sqrt()
+}
+
+// third and fourth standardized moments, kurtosis is reported as excess kurtosis (0 for a gaussian)
+pub fn skewness_kurtosis(plane: &Plane<f32>) -> (f32, f32) {
+    let count = plane.len() as f64;
+    let mean = plane.values().map(|v| *v as f64).sum::<f64>() / count;
+
+    let (mut m2, mut m3, mut m4) = (0.0f64, 0.0f64, 0.0f64);
+    for v in plane.values() {
+        let d = *v as f64 - mean;
+        m2 += d * d;
+        m3 += d * d * d;
+        m4 += d * d * d * d;
+    }
+    m2 /= count;
+    m3 /= count;
+    m4 /= count;
+
+    if m2 == 0.0 {
+        return (0.0, 0.0);
+    }
+    ((m3 / m2.powf(1.5)) as f32, (m4 / m2.powi(2) - 3.0) as f32)
+}
+
+// dynamic range in photographic stops between the bright and dark percentile of linear light
+pub fn dynamic_range_stops(sorted: &[f32], luminance: Luminance) -> f32 {
+    let dark = luminance.to_linear(percentile(sorted, DYNAMIC_RANGE_PERCENTILES.0)).max(MIN_LINEAR);
+    let bright = luminance.to_linear(percentile(sorted, DYNAMIC_RANGE_PERCENTILES.1)).max(MIN_LINEAR);
+    (bright / dark).log2()
+}
+
+// histogram of the plane with `bins` equally wide bins over [0, 1]
+pub fn luminance_histogram(plane: &Plane<f32>, bins: usize) -> Vec<u32> {
+    let mut histogram = vec![0u32; bins];
+    for v in plane.values() {
+        let bin = ((v.clamp(0.0, 1.0) * bins as f32) as usize).min(bins - 1);
+        histogram[bin] += 1;
+    }
+    histogram
+}
+
+// writes the histogram as "bin,count" lines, bins are labeled by their lower edge
+pub fn save_histogram_csv<T: std::fmt::Display>(histogram: &[T], name: &str) {
+    let mut out = String::from("bin,count\n");
+    for (i, count) in histogram.iter().enumerate() {
+        out.push_str(&format!("{},{}\n", i as f32 / histogram.len() as f32, count));
+    }
+    fs::write(name, out).unwrap();
+}
+
+pub fn tonal_features(plane: &Plane<f32>, luminance: Luminance) -> TonalFeatures {
+    let mut sorted: Vec<f32> = plane.values().copied().collect();
+    sorted.sort_by(f32::total_cmp);
+    let count = sorted.len() as f32;
+
+    let highlight = sorted.iter().filter(|v| **v >= HIGHLIGHT_CLIP).count() as f32;
+    let shadow = sorted.iter().filter(|v| **v <= SHADOW_CLIP).count() as f32;
+    let (skewness, kurtosis) = skewness_kurtosis(plane);
+
+    TonalFeatures {
+        mean: mean_plane(plane),
+        percentiles: REPORTED_PERCENTILES.iter().map(|p| (*p, percentile(&sorted, *p))).collect(),
+        highlight_clipping: highlight / count,
+        shadow_clipping: shadow / count,
+        dynamic_range_stops: dynamic_range_stops(&sorted, luminance),
+        skewness,
+        kurtosis,
+        rms_contrast: rms_contrast(plane),
+        std_dev: std_dev(plane),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::linear_to_srgb;
+
+    // the first `share` of the pixels (row major) at `low`, the rest at `high`
+    fn two_level_plane(share: f32, low: f32, high: f32) -> Plane<f32> {
+        let split = (100.0 * share) as usize;
+        Plane::from_fn(10, 10, |x, y| if y * 10 + x < split { low } else { high })
+    }
+
+    #[test]
+    fn percentiles_interpolate_between_ranks() {
+        let sorted = [0.0, 1.0, 2.0, 3.0, 4.0];
+        assert_eq!(percentile(&sorted, 0.0), 0.0);
+        assert_eq!(percentile(&sorted, 25.0), 1.0);
+        assert_eq!(percentile(&sorted, 50.0), 2.0);
+        assert!((percentile(&sorted, 10.0) - 0.4).abs() < 1e-6);
+        assert_eq!(percentile(&sorted, 100.0), 4.0);
+        assert_eq!(percentile(&[], 50.0), 0.0);
+    }
+
+    #[test]
+    fn histogram_bins_by_lower_edge() {
+        let plane = Plane::from_vec(4, 1, vec![0.0, 0.49, 0.5, 1.0]);
+        assert_eq!(luminance_histogram(&plane, 2), vec![2, 2]);
+    }
+
+    #[test]
+    fn moments_of_two_levels() {
+        let features = tonal_features(&two_level_plane(0.5, 0.25, 0.75), Luminance::Rec709);
+        assert!((features.mean - 0.5).abs() < 1e-6);
+        assert!((features.std_dev - 0.25).abs() < 1e-6);
+        assert!(features.skewness.abs() < 1e-6);
+        // a symmetric two point distribution has the smallest possible kurtosis, 1 - 3
+        assert!((features.kurtosis + 2.0).abs() < 1e-5);
+        // sqrt((0.25^2 + 0.75^2) / 2)
+        assert!((features.rms_contrast - 0.3125f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn clipped_pixels_are_counted() {
+        let features = tonal_features(&two_level_plane(0.2, 0.0, 1.0), Luminance::Rec709);
+        assert!((features.shadow_clipping - 0.2).abs() < 1e-6);
+        assert!((features.highlight_clipping - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nan_samples_do_not_panic() {
+        let mut plane = two_level_plane(0.5, 0.25, 0.75);
+        plane[(9, 9)] = f32::NAN;
+        let features = tonal_features(&plane, Luminance::Rec709);
+        // NaN sorts above every number, the median stays between the two levels
+        assert_eq!(features.percentiles.iter().find(|(p, _)| *p == 50.0).map(|(_, v)| *v), Some(0.5));
+    }
+
+    #[test]
+    fn dynamic_range_of_sixteen_to_one_is_four_stops() {
+        // the dark level is 1/16 of the bright one in linear light, as each method reports it
+        let encoded = linear_to_srgb(1.0 / 16.0);
+        for luminance in [Luminance::Rec709, Luminance::Rec709Linear, Luminance::LabL] {
+            let dark = luminance.gray(encoded, encoded, encoded);
+            let stops = tonal_features(&two_level_plane(0.5, dark, 1.0), luminance).dynamic_range_stops;
+            assert!((stops - 4.0).abs() < 1e-3, "{}: {}", luminance.name(), stops);
+        }
+    }
+}
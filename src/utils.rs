@@ -1,4 +1,8 @@
-use image::Rgb32FImage;
+use image::{ImageBuffer, Luma, Rgb32FImage};
+use image::imageops::FilterType;
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex;
+use crate::plane::Plane;
 
 pub fn normalize_value(value: f32, min: f32, max: f32) -> f32 {
     (value - min) / (max - min)
@@ -32,6 +36,29 @@ pub fn _2d_array_to_vec(array: &[[f32;3];3]) -> Vec<Vec<f32>> {
     output
 }
 
+// sRGB transfer function inverse, display value to linear light
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// sRGB transfer function, linear light to display value
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// float sample in [0, 1] to 8 bit, the same rounding image uses for its conversions
+pub fn to_8bit(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
 pub fn save_to_image_f32(image: &Rgb32FImage, name: &str) {
     let imgbuf = image::ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
         let pixel = image.get_pixel(x, y);
@@ -57,28 +84,18 @@ pub fn std_dev(values: &Vec<f32>) -> f32 {
     (sum / values.len() as f32).sqrt()
 }
 
-pub fn std_dev_2d_vec(values: &Vec<Vec<f32>>) -> f32 {
-    let mut sum = 0.0;
-    for i in 0..values.len() {
-        for j in 0..values[0].len() {
-            sum += values[i][j].powi(2);
-        }
-    }
-    (sum / (values.len() * values[0].len()) as f32).sqrt()
+// note: the mean is not subtracted, this is the root mean square of the values
+pub fn std_dev_plane(values: &Plane<f32>) -> f32 {
+    let sum: f32 = values.values().map(|v| v.powi(2)).sum();
+    (sum / values.len() as f32).sqrt()
 }
 
 pub fn mean(values: &Vec<f32>) -> f32 {
     values.iter().sum::<f32>() / values.len() as f32
 }
 
-pub fn mean_2d_vec(values: &Vec<Vec<f32>>) -> f32 {
-    let mut sum = 0.0;
-    for vec in values {
-        for val in vec {
-            sum += val;
-        }
-    }
-    sum / (values.len() * values[0].len()) as f32
+pub fn mean_plane(values: &Plane<f32>) -> f32 {
+    values.mean()
 }
 
 pub const SOBEL_X: [[f32;3];3] = [
@@ -109,4 +126,221 @@ pub const GAUSS_SMOOTH: [[f32;3];3] = [
     [1.0 / 16.0, 2.0  / 16.0, 1.0 / 16.0],
     [2.0 / 16.0, 4.0 / 16.0, 2.0 / 16.0],
     [1.0 / 16.0, 2.0 / 16.0, 1.0 / 16.0],
-];
\ No newline at end of file
+];
+
+// all eigenvalues of a symmetric matrix using the cyclic jacobi method
+// the matrix is small (patch covariances etc.) so the O(n^3) sweeps are fine
+#[allow(clippy::needless_range_loop)]
+pub fn symmetric_eigenvalues(matrix: &[Vec<f64>]) -> Vec<f64> {
+    let n = matrix.len();
+    let mut a: Vec<Vec<f64>> = matrix.to_vec();
+
+    for _sweep in 0..100 {
+        let mut off_diagonal = 0.0;
+        for i in 0..n {
+            for j in (i + 1)..n {
+                off_diagonal += a[i][j].powi(2);
+            }
+        }
+        if off_diagonal < 1e-18 {
+            break;
+        }
+
+        for p in 0..n {
+            for q in (p + 1)..n {
+                if a[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta.powi(2) + 1.0).sqrt());
+                let c = 1.0 / (t.powi(2) + 1.0).sqrt();
+                let s = t * c;
+
+                for k in 0..n {
+                    let akp = a[k][p];
+                    let akq = a[k][q];
+                    a[k][p] = c * akp - s * akq;
+                    a[k][q] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let apk = a[p][k];
+                    let aqk = a[q][k];
+                    a[p][k] = c * apk - s * aqk;
+                    a[q][k] = s * apk + c * aqk;
+                }
+            }
+        }
+    }
+
+    (0..n).map(|i| a[i][i]).collect()
+}
+
+// lanczos approximation of the gamma function (g = 7, n = 9), good to ~15 digits for x > 0
+pub fn gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x < 0.5 {
+        // reflection formula
+        return std::f64::consts::PI / ((std::f64::consts::PI * x).sin() * gamma(1.0 - x));
+    }
+
+    let x = x - 1.0;
+    let mut a = COEFFICIENTS[0];
+    let t = x + G + 0.5;
+    for (i, c) in COEFFICIENTS.iter().enumerate().skip(1) {
+        a += c / (x + i as f64);
+    }
+    (2.0 * std::f64::consts::PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * a
+}
+
+// normalized 1d gaussian kernel of length 2 * radius + 1
+pub fn gaussian_kernel(sigma: f32, radius: usize) -> Vec<f32> {
+    let kernel: Vec<f32> = (0..(2 * radius + 1))
+        .map(|i| {
+            let x = i as f32 - radius as f32;
+            (-(x * x) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter().map(|k| k / sum).collect()
+}
+
+// separable convolution of a plane, borders are replicated
+pub fn separable_filter(plane: &Plane<f32>, kernel: &[f32]) -> Plane<f32> {
+    let (width, height) = (plane.width(), plane.height());
+    let radius = (kernel.len() / 2) as i32;
+
+    let mut horizontal: Plane<f32> = Plane::new(width, height);
+    for y in 0..height {
+        let row = plane.row(y);
+        for (x, out) in horizontal.row_mut(y).iter_mut().enumerate() {
+            *out = kernel.iter().enumerate().map(|(k, weight)| {
+                let xx = (x as i32 + k as i32 - radius).clamp(0, width as i32 - 1);
+                row[xx as usize] * weight
+            }).sum();
+        }
+    }
+
+    let mut output: Plane<f32> = Plane::new(width, height);
+    for y in 0..height {
+        for (k, weight) in kernel.iter().enumerate() {
+            let yy = (y as i32 + k as i32 - radius).clamp(0, height as i32 - 1);
+            let source = horizontal.row(yy as usize);
+            for (out, v) in output.row_mut(y).iter_mut().zip(source.iter()) {
+                *out += v * weight;
+            }
+        }
+    }
+    output
+}
+
+// resizes a float plane with one of image's filters. image clamps float pixels to [0, 1]
+// while resampling, so the values are mapped into that range and back around the resize
+pub fn resize_plane(plane: &Plane<f32>, width: u32, height: u32, filter: FilterType) -> Plane<f32> {
+    let (min, max) = (plane.min(), plane.max());
+    let range = if max > min { max - min } else { 1.0 };
+
+    let buffer: ImageBuffer<Luma<f32>, Vec<f32>> = ImageBuffer::from_fn(
+        plane.width() as u32,
+        plane.height() as u32,
+        |x, y| Luma([(plane[(x as usize, y as usize)] - min) / range]),
+    );
+    let resized = image::imageops::resize(&buffer, width, height, filter);
+
+    Plane::from_vec(width as usize, height as usize, resized.into_raw().iter().map(|v| v * range + min).collect())
+}
+
+// in place 2d fft of a row major width x height buffer, rows first then columns.
+// the inverse is not normalized, divide by width * height to get the original back
+pub fn fft_2d(data: &mut [Complex<f32>], width: usize, height: usize, inverse: bool) {
+    let mut planner = FftPlanner::<f32>::new();
+    let (row_fft, column_fft) = if inverse {
+        (planner.plan_fft_inverse(width), planner.plan_fft_inverse(height))
+    } else {
+        (planner.plan_fft_forward(width), planner.plan_fft_forward(height))
+    };
+
+    for row in data.chunks_exact_mut(width) {
+        row_fft.process(row);
+    }
+
+    let mut column = vec![Complex::new(0.0, 0.0); height];
+    for x in 0..width {
+        for (y, value) in column.iter_mut().enumerate() {
+            *value = data[y * width + x];
+        }
+        column_fft.process(&mut column);
+        for (y, value) in column.iter().enumerate() {
+            data[y * width + x] = *value;
+        }
+    }
+}
+
+// scales the plane linearly so its values span [0, 1], a constant plane becomes all zeros
+pub fn normalize_plane(plane: &mut Plane<f32>) {
+    let (min, max) = (plane.min(), plane.max());
+    for v in plane.values_mut() {
+        *v = if max > min { (*v - min) / (max - min) } else { 0.0 };
+    }
+}
+
+// least squares fit of y = slope * x + intercept, returns (slope, intercept, r squared)
+pub fn linear_regression(xs: &[f64], ys: &[f64]) -> (f64, f64, f64) {
+    let n = xs.len() as f64;
+    if xs.len() < 2 {
+        return (0.0, ys.first().copied().unwrap_or(0.0), 0.0);
+    }
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = ys.iter().sum::<f64>() / n;
+
+    let mut sxx = 0.0;
+    let mut sxy = 0.0;
+    let mut syy = 0.0;
+    for (x, y) in xs.iter().zip(ys.iter()) {
+        sxx += (x - mean_x).powi(2);
+        sxy += (x - mean_x) * (y - mean_y);
+        syy += (y - mean_y).powi(2);
+    }
+    if sxx == 0.0 {
+        return (0.0, mean_y, 0.0);
+    }
+
+    let slope = sxy / sxx;
+    let intercept = mean_y - slope * mean_x;
+    // all points on a horizontal line are explained perfectly
+    let r_squared = if syy == 0.0 { 1.0 } else { sxy * sxy / (sxx * syy) };
+    (slope, intercept, r_squared)
+}
+
+// deterministic pseudo random numbers for test fixtures, a 64-bit linear congruential generator
+// with the constants of Knuth's MMIX. The low bits are poor, callers take the high ones
+#[cfg(test)]
+pub struct Lcg(pub u64);
+
+#[cfg(test)]
+impl Lcg {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        self.0
+    }
+
+    pub fn byte(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    // in [0, 1), 24 bits like an f32 mantissa
+    pub fn unit(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}